@@ -0,0 +1,107 @@
+//! How loading a dialogue and jumping around in it scale with its size.
+//!
+//! The dialogue is made up on the spot, with every section leading to a few
+//! others, which is roughly what a big game's files look like.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/talkie_core/mod.rs"]
+mod talkie_core;
+
+use talkie_core::Dialogue;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 20_000];
+
+/// A dialogue file with the given number of sections.
+fn synthetic(sections: usize) -> String {
+    let mut src = String::from("[variables]\ngold = 10\n");
+    for idx in 0..sections {
+        let far = (idx * 7 + 3) % sections;
+        let near = (idx + 1) % sections;
+        let aside = (idx * 13 + 5) % sections;
+        src.push_str(&format!(
+            r#"
+[[section]]
+id = "s{idx}"
+passages = ["Section {idx}, with {{gold}} gold.", "Been here {{visits.s{idx}}} times."]
+choices = [
+    {{ label = "Far", goto = "s{far}", if = "seen('s{near}')" }},
+    {{ label = "Near", goto = "s{near}" }},
+    {{ label = "Aside", call = "s{aside}", once = true }},
+]
+"#
+        ));
+    }
+    src
+}
+
+/// Some section ids to jump to, spread across the whole file.
+fn targets(sections: usize) -> Vec<String> {
+    (0..1_000)
+        .map(|n| format!("s{}", (n * 7_919) % sections))
+        .collect()
+}
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    for sections in SIZES {
+        let src = synthetic(sections);
+        group.bench_with_input(BenchmarkId::from_parameter(sections), &src, |b, src| {
+            b.iter(|| Dialogue::from_slice(black_box(src.as_bytes())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn jump(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jump");
+    for sections in SIZES {
+        let dialogue = Dialogue::from_slice(synthetic(sections).as_bytes()).unwrap();
+        let targets = targets(sections);
+        group.bench_with_input(
+            BenchmarkId::new("index", sections),
+            &targets,
+            |b, targets| {
+                b.iter(|| {
+                    for id in targets {
+                        black_box(dialogue.section_index(id));
+                    }
+                })
+            },
+        );
+        // What every jump used to cost, for comparison.
+        group.bench_with_input(
+            BenchmarkId::new("scan", sections),
+            &targets,
+            |b, targets| {
+                b.iter(|| {
+                    for id in targets {
+                        black_box(
+                            dialogue
+                                .passage_groups
+                                .iter()
+                                .position(|group| group.id.as_deref() == Some(id)),
+                        );
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn reachable(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reachable");
+    for sections in SIZES {
+        let dialogue = Dialogue::from_slice(synthetic(sections).as_bytes()).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(sections), |b| {
+            b.iter(|| dialogue.graph().reachable([0]))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, load, jump, reachable);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! What revealing a glyph costs each frame, for passages of different lengths.
+//!
+//! `rebuild` is how the dialogue box used to be updated, building the whole
+//! visible string again every frame. `append` only adds what's new, so it
+//! should cost the same no matter how long the passage is. Laying out the
+//! changed `Text` is left to bevy and isn't measured here.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/talkie_core/mod.rs"]
+mod talkie_core;
+
+use talkie_core::Revealed;
+
+const LENGTHS: [usize; 3] = [100, 1_000, 10_000];
+
+/// A passage with the given number of glyphs, not all of them ascii.
+fn passage(glyphs: usize) -> String {
+    "Kept you waiting, huh? Ça va. "
+        .chars()
+        .cycle()
+        .take(glyphs)
+        .collect()
+}
+
+fn reveal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reveal");
+    for glyphs in LENGTHS {
+        let text = passage(glyphs);
+        // Halfway through the passage, one more glyph comes in.
+        let head = glyphs / 2;
+        group.bench_with_input(BenchmarkId::new("rebuild", glyphs), &text, |b, text| {
+            b.iter(|| black_box(text.chars().take(head + 1).collect::<String>()))
+        });
+        group.bench_with_input(BenchmarkId::new("append", glyphs), &text, |b, text| {
+            b.iter_batched_ref(
+                || {
+                    let mut revealed = Revealed::default();
+                    let mut shown = String::with_capacity(text.len());
+                    revealed.catch_up(text, head, &mut shown);
+                    (revealed, shown)
+                },
+                |(revealed, shown)| revealed.catch_up(text, head + 1, shown),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reveal);
+criterion_main!(benches);
@@ -0,0 +1,230 @@
+//! Checks the dialogue files ahead of time, so mistakes in them fail the build
+//! rather than turning up mid-conversation.
+//!
+//! Each file in `assets/dialogue` (and the directories under it) also gets a
+//! list of constants for its section ids, which `include_dialogue!` pulls into
+//! the game.
+//!
+//! With the `embedded-assets` feature, this also lists the files to build into
+//! the binary for `EmbeddedAssetsPlugin`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+#[allow(dead_code, unused_imports)]
+#[path = "src/talkie_core/mod.rs"]
+mod talkie_core;
+
+const ASSETS_DIR: &str = "assets";
+const DIALOGUE_DIR: &str = "dialogue";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/talkie_core");
+    println!("cargo:rerun-if-changed={ASSETS_DIR}/{DIALOGUE_DIR}");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    let mut files = vec![];
+    walk(&Path::new(ASSETS_DIR).join(DIALOGUE_DIR), &mut files).unwrap();
+    files.sort();
+
+    let mut errors = vec![];
+    let mut dialogues = vec![];
+    let mut others = vec![];
+    for path in files {
+        let asset_path = asset_path(&path);
+        if !asset_path.ends_with(".toml") || asset_path.ends_with(".theme.toml") {
+            others.push(asset_path);
+            continue;
+        }
+        match generate(&path, &asset_path) {
+            Ok((dialogue, code)) => {
+                let out = out_dir.join(format!("{asset_path}.rs"));
+                fs::create_dir_all(out.parent().unwrap()).unwrap();
+                fs::write(out, code).unwrap();
+                dialogues.push((asset_path, dialogue));
+            }
+            Err(e) => errors.push(format!("{asset_path}: {e}")),
+        }
+    }
+    if !errors.is_empty() {
+        panic!("invalid dialogue:\n{}", errors.join("\n"));
+    }
+
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some() {
+        embed(&dialogues, others).unwrap();
+    }
+}
+
+/// Every file in `dir` and the directories under it.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The path the game loads a file in `assets` by, e.g. `dialogue/npcs/shop.toml`.
+fn asset_path(path: &Path) -> String {
+    let relative = path.strip_prefix(ASSETS_DIR).unwrap();
+    let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+    parts.join("/")
+}
+
+/// Write out the table of files for `EmbeddedAssetsPlugin`: everything in
+/// `assets/dialogue` (`others` being what isn't dialogue), the voice clips
+/// the dialogue uses, every theme in `assets`, and the fonts and images the
+/// themes use.
+///
+/// With `compiled-dialogue` as well, each dialogue file is compiled and its
+/// compiled copy embedded alongside it.
+fn embed(dialogues: &[(String, talkie_core::Dialogue)], others: Vec<String>) -> anyhow::Result<()> {
+    let assets = Path::new(&env::var("CARGO_MANIFEST_DIR")?).join(ASSETS_DIR);
+    let out_dir = Path::new(&env::var("OUT_DIR")?).join("embedded");
+    let compile = env::var_os("CARGO_FEATURE_COMPILED_DIALOGUE").is_some();
+
+    // By the path they're loaded with. Anything added later wins, so a
+    // compiled copy made here replaces any left lying around from before.
+    let mut files: BTreeMap<String, PathBuf> = others
+        .into_iter()
+        .map(|asset_path| (asset_path.clone(), assets.join(asset_path)))
+        .collect();
+    for (asset_path, dialogue) in dialogues {
+        let voices = dialogue
+            .passage_groups
+            .iter()
+            .flat_map(|g| g.voices.iter().flatten());
+        for voice in voices {
+            let file = assets.join(voice);
+            if file.is_file() {
+                files.insert(voice.clone(), file);
+            } else {
+                println!("cargo:warning={asset_path} uses {voice}, which doesn't exist");
+            }
+        }
+
+        let source = assets.join(asset_path);
+        if compile {
+            let compiled_path = Path::new(asset_path)
+                .with_extension(talkie_core::COMPILED_EXTENSION)
+                .to_string_lossy()
+                .into_owned();
+            let out = out_dir.join(&compiled_path);
+            fs::create_dir_all(out.parent().unwrap())?;
+            fs::write(&out, dialogue.to_compiled(&fs::read(&source)?)?)?;
+            files.insert(compiled_path, out);
+        }
+        files.insert(asset_path.clone(), source);
+    }
+
+    for entry in fs::read_dir(&assets)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if !name.ends_with(".theme.toml") {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+        let theme: toml::Value = toml::from_slice(&fs::read(&path)?)?;
+        let mut used = vec![];
+        theme_files(&theme, &mut used);
+        for asset_path in used {
+            let file = assets.join(&asset_path);
+            if file.is_file() {
+                files.insert(asset_path, file);
+            } else {
+                println!("cargo:warning={name} uses {asset_path}, which doesn't exist");
+            }
+        }
+        files.insert(name, path);
+    }
+    let mut code = String::from("// Generated by build.rs.\n&[\n");
+    for (asset_path, file) in files {
+        writeln!(
+            code,
+            "    ({asset_path:?}, include_bytes!({:?})),",
+            file.display()
+        )?;
+    }
+    code.push_str("]\n");
+    fs::write(
+        Path::new(&env::var("OUT_DIR")?).join("embedded_assets.rs"),
+        code,
+    )?;
+    Ok(())
+}
+
+/// Collect the fonts and images a theme refers to.
+fn theme_files(value: &toml::Value, files: &mut Vec<String>) {
+    let toml::Value::Table(table) = value else {
+        return;
+    };
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("font" | "portrait" | "image", toml::Value::String(path)) => files.push(path.clone()),
+            _ => theme_files(value, files),
+        }
+    }
+}
+
+/// Check the dialogue at `path`, and write out its constants. The checked
+/// dialogue comes back too, for embedding.
+fn generate(path: &Path, asset_path: &str) -> anyhow::Result<(talkie_core::Dialogue, String)> {
+    let dialogue = talkie_core::Dialogue::from_slice(&fs::read(path)?)?;
+    for module in dialogue.modules() {
+        let included = dialogue.module_path(module).expect("checked on load");
+        let included_path = path.with_file_name(included);
+        if !included_path.is_file() {
+            anyhow::bail!("`{module}` is included from {included}, which doesn't exist");
+        }
+        let other = talkie_core::Dialogue::from_slice(&fs::read(&included_path)?)
+            .map_err(|e| anyhow::anyhow!("{included}: {e}"))?;
+        dialogue.check_module_targets(module, &other)?;
+    }
+
+    let mut code = String::new();
+    writeln!(code, "// Generated from {asset_path} by build.rs.")?;
+    writeln!(code, "#[allow(dead_code)]")?;
+    writeln!(code, "pub const PATH: &str = {asset_path:?};")?;
+
+    let mut names = HashMap::new();
+    for id in dialogue
+        .passage_groups
+        .iter()
+        .filter_map(|g| g.id.as_deref())
+    {
+        let name = const_name(id);
+        if let Some(other) = names.insert(name.clone(), id) {
+            anyhow::bail!("section ids `{other}` and `{id}` would both be named {name}");
+        }
+        writeln!(code, "#[allow(dead_code)]")?;
+        writeln!(
+            code,
+            "pub const {name}: crate::plugin::SectionId = crate::plugin::SectionId {{ path: PATH, id: {id:?} }};"
+        )?;
+    }
+    Ok((dialogue, code))
+}
+
+/// Turn a section id into a name for its constant, e.g. `"shop-counter"` into
+/// `SHOP_COUNTER`.
+fn const_name(id: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("_{name}"),
+    }
+}
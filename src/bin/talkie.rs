@@ -0,0 +1,94 @@
+//! Tools for working with dialogue files.
+//!
+//! ```text
+//! talkie compile <path>...
+//! ```
+//!
+//! Checks each dialogue file, or every `.toml` file in a directory, and saves
+//! a compiled copy next to it with the `.talkie` extension. Builds with the
+//! `compiled-dialogue` feature load those instead of the TOML.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../talkie_core/mod.rs"]
+mod talkie_core;
+
+use talkie_core::{Dialogue, COMPILED_EXTENSION};
+
+const USAGE: &str = "usage: talkie compile <path>...";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, paths)) if command == "compile" && !paths.is_empty() => compile(paths),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn compile(paths: &[String]) -> Result<()> {
+    let mut files = vec![];
+    for path in paths.iter().map(Path::new) {
+        if path.is_dir() {
+            files.extend(dialogue_files(path)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        match compile_file(file) {
+            Ok(out) => println!("Compiled {} to {}", file.display(), out.display()),
+            Err(e) => {
+                eprintln!("{}: {e:#}", file.display());
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} files couldn't be compiled", files.len());
+    }
+    Ok(())
+}
+
+/// The dialogue files in a directory, leaving out themes.
+fn dialogue_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in dir
+        .read_dir()
+        .with_context(|| format!("reading {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(".toml") && !name.ends_with(".theme.toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Check and compile a single file, returning where the compiled copy went.
+fn compile_file(path: &Path) -> Result<PathBuf> {
+    let source = std::fs::read(path)?;
+    let dialogue = Dialogue::from_slice(&source)?;
+    for idx in dialogue.graph().unreachable() {
+        eprintln!("{}: section {idx} can never be reached", path.display());
+    }
+    let out = path.with_extension(COMPILED_EXTENSION);
+    std::fs::write(&out, dialogue.to_compiled(&source)?)?;
+    Ok(out)
+}
@@ -1,10 +1,20 @@
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 use std::time::Duration;
+use talkie_core::Value;
 
 mod plugin;
 mod talkie_core;
 
+/// Section ids for the demo's dialogue, checked at build time.
+mod ambient {
+    crate::include_dialogue!("dialogue/ambient.toml");
+}
+
+mod mgs3 {
+    crate::include_dialogue!("dialogue/mgs3-body-snatchers.toml");
+}
+
 #[derive(Component)]
 struct GameCamera;
 
@@ -13,16 +23,230 @@ fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default()).insert(GameCamera);
 }
 
+/// Kick off the demo conversation.
+fn start_conversation(ass: Res<AssetServer>, mut events: EventWriter<plugin::StartConversation>) {
+    events.send(plugin::StartConversation {
+        dialogue: ass.load(mgs3::PATH),
+        section: None,
+    });
+}
+
+/// Pick the conversation back up after walking away from it.
+fn resume_conversation(
+    keys: Res<Input<KeyCode>>,
+    events: EventWriter<plugin::StartConversation>,
+    ass: Res<AssetServer>,
+) {
+    if keys.just_pressed(KeyCode::Return) {
+        start_conversation(ass, events);
+    }
+}
+
+/// Where the demo keeps its save file.
+const SAVE_PATH: &str = "talkie-save.toml";
+
+/// Carry on counting visits from the last save, if there is one.
+fn load_visits(mut visits: ResMut<plugin::DialogueVisits>) {
+    let Ok(save) = std::fs::read_to_string(SAVE_PATH) else {
+        return;
+    };
+    match toml::from_str(&save) {
+        Ok(loaded) => *visits = loaded,
+        Err(e) => eprintln!("Couldn't read {SAVE_PATH}: {e}"),
+    }
+}
+
+/// Save the visit counts when F5 is pressed.
+fn save_visits(keys: Res<Input<KeyCode>>, visits: Res<plugin::DialogueVisits>) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+    let saved = toml::to_string(&*visits)
+        .map_err(anyhow::Error::from)
+        .and_then(|save| Ok(std::fs::write(SAVE_PATH, save)?));
+    match saved {
+        Ok(()) => println!("Saved to {SAVE_PATH}"),
+        Err(e) => eprintln!("Couldn't save to {SAVE_PATH}: {e}"),
+    }
+}
+
+/// The codec ringing before the conversation starts.
+#[derive(Resource)]
+struct CodecCall {
+    timer: Timer,
+    command: Option<plugin::CommandId>,
+}
+
+fn ring_codec(world: &mut World, command: &plugin::DialogueCommand) {
+    world.insert_resource(CodecCall {
+        timer: Timer::from_seconds(1.0, TimerMode::Once),
+        command: command.wait,
+    });
+}
+
+/// Let the conversation carry on once the codec stops ringing.
+fn answer_codec(
+    mut commands: Commands,
+    time: Res<Time>,
+    call: Option<ResMut<CodecCall>>,
+    mut finished: EventWriter<plugin::CommandFinished>,
+) {
+    let Some(mut call) = call else {
+        return;
+    };
+    if call.timer.tick(time.delta()).finished() {
+        if let Some(id) = call.command {
+            finished.send(plugin::CommandFinished(id));
+        }
+        commands.remove_resource::<CodecCall>();
+    }
+}
+
+/// Someone standing around in the world, making small talk.
+#[derive(Component)]
+struct Npc {
+    /// The section of `ambient.toml` they recite, also used as their bark tag.
+    section: plugin::SectionId,
+}
+
+#[derive(Resource)]
+struct Chatter {
+    dialogue: Handle<plugin::Dialogue>,
+    timer: Timer,
+    /// Every other round the npcs bark instead of reciting their section.
+    bark: bool,
+    /// The merchant sells a loaf every round.
+    loaves: i32,
+}
+
+/// Who the player is, as far as the dialogue is concerned.
+#[derive(Clone)]
+struct Player {
+    name: &'static str,
+    persuasion: i32,
+}
+
+impl plugin::VariableResolver for Player {
+    fn resolve(&self, name: &str) -> Option<Value> {
+        match name {
+            "player_name" => Some(self.name.into()),
+            _ => None,
+        }
+    }
+}
+
+impl plugin::StatProvider for Player {
+    fn stat(&self, name: &str) -> Option<i32> {
+        match name {
+            "persuasion" => Some(self.persuasion),
+            _ => None,
+        }
+    }
+}
+
+fn setup_npcs(
+    mut commands: Commands,
+    ass: Res<AssetServer>,
+    mut variables: ResMut<plugin::DialogueVariables>,
+    mut checks: ResMut<plugin::SkillChecks>,
+) {
+    let player = Player {
+        name: "Snake",
+        persuasion: 2,
+    };
+    variables.add_resolver(player.clone());
+    checks.set_provider(player);
+    // The only thing the player is carrying.
+    variables.add_function("has_item", |args: &[Value]| match args {
+        [Value::Text(item)] => Some((item == "keycard").into()),
+        _ => None,
+    });
+
+    let dialogue = ass.load(ambient::PATH);
+    for (x, section, color) in [
+        (-200.0, ambient::GUARD, Color::rgb(0.3, 0.5, 0.8)),
+        (200.0, ambient::MERCHANT, Color::rgb(0.8, 0.6, 0.3)),
+    ] {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(32.0)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(x, 150.0, 0.0),
+                ..default()
+            },
+            Npc { section },
+            plugin::Barker {
+                dialogue: dialogue.clone(),
+            },
+        ));
+    }
+    commands.insert_resource(Chatter {
+        dialogue,
+        timer: Timer::from_seconds(8.0, TimerMode::Repeating),
+        bark: false,
+        loaves: 12,
+    });
+}
+
+/// Every so often, have each npc say their piece.
+fn npc_chatter(
+    time: Res<Time>,
+    mut chatter: ResMut<Chatter>,
+    npcs: Query<(Entity, &Npc)>,
+    mut bubbles: EventWriter<plugin::StartBubble>,
+    mut barks: EventWriter<plugin::Bark>,
+    mut variables: ResMut<plugin::DialogueVariables>,
+) {
+    if !chatter.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    chatter.loaves = (chatter.loaves - 1).max(1);
+    variables.set("loaves", chatter.loaves);
+    for (entity, npc) in &npcs {
+        if chatter.bark {
+            barks.send(plugin::Bark {
+                entity,
+                tag: npc.section.id.to_string(),
+            });
+        } else {
+            bubbles.send(plugin::StartBubble {
+                anchor: entity,
+                dialogue: chatter.dialogue.clone(),
+                section: Some(npc.section),
+            });
+        }
+    }
+    chatter.bark = !chatter.bark;
+}
+
 fn main() {
+    let plugins = DefaultPlugins.set(AssetPlugin {
+        // Pick up edits to themes and dialogue while the game is running.
+        watch_for_changes: true,
+        ..default()
+    });
+    #[cfg(feature = "embedded-assets")]
+    let plugins = plugins.add_before::<AssetPlugin, _>(plugin::EmbeddedAssetsPlugin);
+
     App::new()
-        .add_plugins(DefaultPlugins)
+        .add_plugins(plugins)
         .add_fixed_timestep(
             Duration::from_millis(125),
             // give it a label
             "my_fixed_update",
         )
-        .add_plugin(plugin::TalkiePlugin)
+        .add_plugin(plugin::TalkiePlugin::default().with_command("codec_call", ring_codec))
         // setup our camera globally (for UI) at startup and keep it alive at all times
         .add_startup_system(setup_camera)
+        .add_startup_system(start_conversation)
+        .add_startup_system(setup_npcs)
+        .add_startup_system(load_visits)
+        .add_system(save_visits)
+        .add_system(npc_chatter)
+        .add_system(answer_codec)
+        .add_system(resume_conversation.run_if_not(plugin::dialogue_active))
         .run();
 }
@@ -0,0 +1,190 @@
+//! A record of everything said so far, which the player can scroll back
+//! through.
+
+use crate::plugin::billboard::{Billboard, Modal};
+use crate::plugin::phase::DialoguePhase;
+use crate::plugin::theme::TalkieTheme;
+use crate::plugin::Action;
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::action_state::ActionState;
+
+pub struct BacklogPlugin;
+
+impl Plugin for BacklogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DialogueLog>()
+            .add_system(toggle_backlog.run_if(backlog_allowed))
+            .add_system_set(
+                ConditionSet::new()
+                    .run_if_resource_exists::<BacklogView>()
+                    .with_system(backlog_input)
+                    .with_system(render_backlog)
+                    .into(),
+            );
+    }
+}
+
+/// How many entries fit in the overlay at once.
+const VISIBLE_ENTRIES: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    /// A passage that was revealed in full.
+    Passage {
+        speaker: Option<String>,
+        text: String,
+        voice: Option<Handle<AudioSource>>,
+    },
+    /// The label of a choice the player picked.
+    Choice { label: String },
+}
+
+/// Everything revealed so far, oldest first.
+#[derive(Resource, Debug, Default)]
+pub struct DialogueLog(pub Vec<LogEntry>);
+
+/// Exists while the backlog overlay is open.
+///
+/// Systems that react to input elsewhere in the plugin should not run while
+/// this is present.
+#[derive(Resource)]
+pub struct BacklogView {
+    /// Index of the highlighted entry in the log.
+    selected: usize,
+}
+
+#[derive(Component)]
+struct BacklogOverlay;
+
+#[derive(Component)]
+struct BacklogText;
+
+/// The backlog can be opened once the conversation has loaded, but not while
+/// the player is typing, since its key would end up in the text instead.
+fn backlog_allowed(query: Query<&DialoguePhase, With<Modal>>) -> bool {
+    query
+        .iter()
+        .any(|phase| !matches!(phase, DialoguePhase::Loading | DialoguePhase::Input))
+}
+
+fn toggle_backlog(
+    mut commands: Commands,
+    log: Res<DialogueLog>,
+    view: Option<Res<BacklogView>>,
+    action_state: Query<&ActionState<Action>, With<Modal>>,
+    overlay: Query<Entity, With<BacklogOverlay>>,
+) {
+    let action_state = action_state.single();
+    if view.is_some() {
+        if action_state.just_pressed(Action::Backlog) || action_state.just_pressed(Action::Cancel) {
+            commands.remove_resource::<BacklogView>();
+            for e in overlay.iter() {
+                commands.entity(e).despawn_recursive();
+            }
+        }
+    } else if action_state.just_pressed(Action::Backlog) && !log.0.is_empty() {
+        commands.insert_resource(BacklogView {
+            selected: log.0.len() - 1,
+        });
+        setup_backlog(&mut commands);
+    }
+}
+
+fn backlog_input(
+    log: Res<DialogueLog>,
+    mut view: ResMut<BacklogView>,
+    action_state: Query<&ActionState<Action>, With<Modal>>,
+    audio: Res<Audio>,
+) {
+    let action_state = action_state.single();
+
+    if action_state.just_pressed(Action::Up) && view.selected > 0 {
+        view.selected -= 1;
+    }
+    if action_state.just_pressed(Action::Down) && view.selected < log.0.len() - 1 {
+        view.selected += 1;
+    }
+    if action_state.just_pressed(Action::Confirm) {
+        if let Some(LogEntry::Passage {
+            voice: Some(voice), ..
+        }) = log.0.get(view.selected)
+        {
+            audio.play(voice.clone());
+        }
+    }
+}
+
+fn render_backlog(
+    log: Res<DialogueLog>,
+    view: Res<BacklogView>,
+    mut text: Query<&mut Text, With<BacklogText>>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    billboard: Query<&Billboard, With<Modal>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    let Ok(mut text) = text.get_single_mut() else {
+        // The overlay is spawned by commands, so it might not exist until the
+        // next tick.
+        return;
+    };
+
+    let theme = themes
+        .get(&billboard.single().theme)
+        .cloned()
+        .unwrap_or_default();
+    let style = theme.fonts.body.text_style(&ass);
+    let first = (view.selected + 1).saturating_sub(VISIBLE_ENTRIES);
+    text.sections = log.0[first..=view.selected]
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            let color = if first + idx == view.selected {
+                Color::YELLOW
+            } else {
+                style.color
+            };
+            let value = match entry {
+                LogEntry::Passage { speaker, text, .. } => match speaker {
+                    Some(speaker) => format!("{speaker}: {}\n\n", text.trim()),
+                    None => format!("{}\n\n", text.trim()),
+                },
+                LogEntry::Choice { label } => format!("> {label}\n\n"),
+            };
+            TextSection::new(
+                value,
+                TextStyle {
+                    color,
+                    ..style.clone()
+                },
+            )
+        })
+        .collect();
+}
+
+fn setup_backlog(commands: &mut Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    padding: UiRect::all(Val::Px(40.0)),
+                    align_items: AlignItems::FlexEnd,
+                    ..default()
+                },
+                // Draw over the billboard.
+                z_index: ZIndex::Global(1),
+                ..default()
+            },
+            BacklogOverlay,
+        ))
+        .with_children(|parent| {
+            // Filled in by `render_backlog`.
+            parent.spawn((TextBundle::from_sections([]), BacklogText));
+        });
+}
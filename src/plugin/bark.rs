@@ -0,0 +1,96 @@
+//! Barks: one-liners an entity says in passing, picked at random from the
+//! dialogue's bark table.
+//!
+//! A bark is shown in a speech bubble, so it never takes input focus.
+
+use crate::plugin::bubble::{spawn_bubble, BubbleSource, SpeechBubble};
+use crate::plugin::memory::DialogueVisits;
+use crate::plugin::theme::TalkieTheme;
+use crate::plugin::variables::DialogueVariables;
+use crate::plugin::{Dialogue, TalkieRng};
+use crate::talkie_core::BarkHistory;
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+pub struct BarkPlugin;
+
+impl Plugin for BarkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Bark>()
+            .init_resource::<BarkMemory>()
+            .add_system(bark_system);
+    }
+}
+
+/// Send this to have an entity say something from the bark pool for `tag`.
+///
+/// Nothing is said if the pool is cooling down.
+pub struct Bark {
+    /// The entity to speak. It needs a `Barker` and a `GlobalTransform`.
+    pub entity: Entity,
+    pub tag: String,
+}
+
+/// Gives an entity a bark table to pick lines from.
+#[derive(Component)]
+pub struct Barker {
+    pub dialogue: Handle<Dialogue>,
+}
+
+/// Cooldowns and recent lines for each bark pool.
+///
+/// These are shared by everyone barking from the same pool, so two guards
+/// won't say the same thing back to back.
+#[derive(Resource, Default)]
+struct BarkMemory(HashMap<(HandleId, String), BarkHistory>);
+
+#[allow(clippy::too_many_arguments)]
+fn bark_system(
+    mut commands: Commands,
+    mut events: EventReader<Bark>,
+    time: Res<Time>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    dialogues: Res<Assets<Dialogue>>,
+    mut rng: ResMut<TalkieRng>,
+    mut memory: ResMut<BarkMemory>,
+    variables: Res<DialogueVariables>,
+    visits: Res<DialogueVisits>,
+    barkers: Query<&Barker>,
+    existing: Query<(Entity, &SpeechBubble)>,
+) {
+    for event in events.iter() {
+        let Ok(barker) = barkers.get(event.entity) else {
+            eprintln!("Can't bark from an entity without a Barker.");
+            continue;
+        };
+        // Barks are throwaway, so there's no point holding on to one until
+        // the dialogue finishes loading.
+        let Some(dialogue) = dialogues.get(&barker.dialogue) else {
+            continue;
+        };
+        let Some(pool) = dialogue.dialogue.barks.get(&event.tag) else {
+            eprintln!("No bark `{}`.", event.tag);
+            continue;
+        };
+
+        let history = memory
+            .0
+            .entry((barker.dialogue.id(), event.tag.clone()))
+            .or_default();
+        let Some(idx) = pool.pick(history, time.elapsed_seconds_f64(), &mut rng.0) else {
+            continue;
+        };
+
+        spawn_bubble(
+            &mut commands,
+            &ass,
+            &themes,
+            &existing,
+            event.entity,
+            barker.dialogue.clone(),
+            BubbleSource::Line(variables.interpolate(&pool.lines[idx].text, dialogue, &visits)),
+        );
+    }
+}
@@ -2,9 +2,16 @@
 //! Still, the general idea was a billboard is the top-level or entrypoint for
 //! the whole dialogue presentation.
 
-use crate::plugin::goto::Goto;
-use crate::plugin::{Action, Dialogue, GameState, DEFAULT_GLYPHS_PER_SEC};
+use crate::plugin::command::PendingCommands;
+use crate::plugin::goto::{CallStack, Goto};
+use crate::plugin::input::load_input_map;
+use crate::plugin::phase::{in_phase, DialoguePhase, NextPhase};
+use crate::plugin::theme::{Backdrop, TalkieTheme, DEFAULT_THEME_PATH};
+use crate::plugin::{Action, Dialogue, SectionId, DEFAULT_GLYPHS_PER_SEC};
+use crate::talkie_core::{Command, Jump, Revealed};
+use bevy::asset::LoadState;
 use bevy::prelude::*;
+use bevy::utils::HashSet;
 use iyes_loopless::prelude::*;
 use leafwing_input_manager::prelude::*;
 
@@ -12,16 +19,22 @@ pub struct BillboardPlugin;
 
 impl Plugin for BillboardPlugin {
     fn build(&self, app: &mut App) {
-        app.add_enter_system(GameState::Loading, setup_billboard)
-            .add_system_set(
-                ConditionSet::new()
-                    .run_in_state(GameState::Loading)
-                    .with_system(wait_for_assets)
-                    .into(),
-            );
+        app.add_event::<StartConversation>()
+            .add_system(setup_billboard)
+            .add_system(wait_for_assets.run_if(in_phase(DialoguePhase::Loading)));
     }
 }
 
+/// Send this to open the billboard and play a dialogue.
+///
+/// Starting a conversation while another is running replaces it.
+pub struct StartConversation {
+    pub dialogue: Handle<Dialogue>,
+    /// The section to start at, or the first section when `None`. It has to
+    /// be one from `dialogue`.
+    pub section: Option<SectionId>,
+}
+
 #[derive(Component, Debug, Default)]
 pub struct Bookmark {
     /// tracks which passage group we're iterating through.
@@ -43,54 +56,176 @@ pub struct PlayHead {
     //   it for changes in the playback system and reset this field at that point.
     pub secs_since_last_reveal: Option<f32>,
     pub fast_forward: bool,
+    /// When set, the rest of the passage is revealed on the next tick.
+    pub skip: bool,
     pub glyphs_per_sec: f32,
 }
 
+impl PlayHead {
+    /// A play head at the very start of a passage, revealing glyphs at the
+    /// rate given by the `TALKIE_SPEED` env var.
+    pub fn from_env() -> Self {
+        PlayHead {
+            head: 0,
+            secs_since_last_reveal: None,
+            fast_forward: false,
+            skip: false,
+            glyphs_per_sec: std::env::var("TALKIE_SPEED")
+                .map(|s| s.parse().expect("invalid speed."))
+                .unwrap_or(DEFAULT_GLYPHS_PER_SEC),
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct SpeakerNameTab;
 
 #[derive(Component)]
 pub struct SpeakerNameText;
 
+/// Shows a picture of the current speaker, when the theme has one for them.
+#[derive(Component)]
+pub struct Portrait;
+
 #[derive(Component)]
 pub struct DialogueText;
 
 #[derive(Component)]
 pub struct Billboard {
     pub dialogue: Handle<Dialogue>,
+    pub theme: Handle<TalkieTheme>,
 }
 
+/// The speaker for the passage being shown.
+///
+/// Only written to when the speaker actually changes, so it can be watched
+/// for changes.
+#[derive(Component, Debug, Default)]
+pub struct CurrentSpeaker(pub Option<String>);
+
+/// The passage being revealed, once any variations have been picked.
+#[derive(Component, Debug, Default)]
+pub struct CurrentPassage {
+    /// Index of the passage within its passage group.
+    pub index: usize,
+    pub text: String,
+    /// How many glyphs `text` has, which is what the play head counts.
+    pub glyphs: usize,
+    pub speaker: Option<String>,
+    /// Commands found in the passage, and how many glyphs in they're run.
+    pub commands: Vec<(usize, Command)>,
+    /// How many of the commands have been run so far.
+    pub sent: usize,
+    /// How much of the text is on screen.
+    pub revealed: Revealed,
+    /// Cleared whenever the bookmark moves on, so the next passage gets
+    /// picked.
+    pub resolved: bool,
+}
+
+/// A line the player character says after picking a choice, played before
+/// the choice's `goto` is followed.
+#[derive(Component, Debug)]
+pub struct SpokenLine {
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Marks the main conversation: the one shown in the billboard, which takes
+/// input focus while it runs.
+///
+/// Other conversations (such as speech bubbles) can run alongside it, but
+/// there's only ever one of these.
+#[derive(Component)]
+pub struct Modal;
+
 #[derive(Component)]
 pub struct Root;
 
-const BILLBOARD_HEIGHT: Val = Val::Px(300.0);
+fn wait_for_assets(
+    mut commands: Commands,
+    ass: Res<Assets<Dialogue>>,
+    server: Res<AssetServer>,
+    query: Query<(Entity, &Billboard, &Goto), With<Modal>>,
+) {
+    if let Ok((conversation, b, goto)) = query.get_single() {
+        if loaded_with_includes(&ass, &server, &b.dialogue) {
+            let dialogue = ass.get(&b.dialogue).expect("dialogue");
+            if dialogue.dialogue.passage_groups.is_empty() {
+                eprintln!("`{}` has no sections to talk through.", dialogue.path);
+                commands.entity(conversation).despawn_recursive();
+                return;
+            }
+            // Starting partway through is just a jump to begin with.
+            let next = if goto.0.is_some() {
+                DialoguePhase::Goto
+            } else {
+                DialoguePhase::Playback
+            };
+            commands.entity(conversation).insert(NextPhase(next));
+        }
+    }
+}
 
-fn wait_for_assets(mut commands: Commands, ass: Res<Assets<Dialogue>>, query: Query<&Billboard>) {
-    if let Ok(b) = query.get_single() {
-        if ass.get(&b.dialogue).is_some() {
-            commands.insert_resource(NextState(GameState::Playback));
-            commands.insert_resource(Goto(None));
+/// Whether a dialogue has loaded, along with the files it includes, the files
+/// those include, and so on, since a jump can end up in any of them.
+///
+/// Included files that failed to load don't hold things up.
+fn loaded_with_includes(
+    dialogues: &Assets<Dialogue>,
+    server: &AssetServer,
+    root: &Handle<Dialogue>,
+) -> bool {
+    let mut visited = HashSet::new();
+    let mut queue = vec![root.clone()];
+    while let Some(handle) = queue.pop() {
+        if !visited.insert(handle.id()) {
+            continue;
+        }
+        match dialogues.get(&handle) {
+            Some(dialogue) => queue.extend(dialogue.modules.values().cloned()),
+            None if handle != *root && server.get_load_state(&handle) == LoadState::Failed => {}
+            None => return false,
         }
     }
+    true
 }
 
 /// Construct the main conversation UI
-fn setup_billboard(mut commands: Commands, ass: Res<AssetServer>) {
-    let dialogue = ass.load("dialogue/mgs3-body-snatchers.toml");
+fn setup_billboard(
+    mut commands: Commands,
+    mut events: EventReader<StartConversation>,
+    ass: Res<AssetServer>,
+    existing: Query<Entity, With<Modal>>,
+) {
+    // Only the latest request matters since there's only room for one.
+    let Some(event) = events.iter().last() else {
+        return;
+    };
+    if let Some(section) = event.section {
+        if !section.is_in(&ass, &event.dialogue) {
+            eprintln!(
+                "Can't start a conversation at `{section}`, which is from {}.",
+                section.path
+            );
+            return;
+        }
+    }
+    for entity in &existing {
+        commands.entity(entity).despawn_recursive();
+    }
 
-    // TODO: load sprites
+    let dialogue = event.dialogue.clone();
+    let theme_handle = ass.load(DEFAULT_THEME_PATH);
+    // The theme won't have loaded yet, so start out with the defaults and let
+    // `apply_theme` catch up.
+    let theme = TalkieTheme::default();
 
     // In amethyst dialogue text and speaker name text were two separate UI
     // entities, handed off when constructing the playback state.
     // Once all the assets were loaded, the playback state is initialized and
     // the state machine transitioned to it.
 
-    let style = TextStyle {
-        font: ass.load("Sansation-Regular.ttf"),
-        font_size: 20.0,
-        color: Color::WHITE,
-    };
-
     commands
         .spawn((
             NodeBundle {
@@ -105,43 +240,23 @@ fn setup_billboard(mut commands: Commands, ass: Res<AssetServer>) {
                 // Stores "which actions are currently pressed"
                 action_state: ActionState::default(),
                 // Describes how to convert from player inputs into those actions
-                input_map: InputMap::new([
-                    (KeyCode::Space, Action::Confirm),
-                    (KeyCode::Return, Action::Confirm),
-                    (KeyCode::Up, Action::Up),
-                    (KeyCode::W, Action::Up),
-                    (KeyCode::Down, Action::Down),
-                    (KeyCode::S, Action::Down),
-                ]),
+                input_map: load_input_map(),
             },
         ))
         .with_children(|parent| {
-            let billboard_color = Color::rgb(0.4, 0.4, 0.6);
             parent
                 .spawn(NodeBundle {
-                    background_color: BackgroundColor(billboard_color),
-                    style: Style {
-                        position: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Auto, Val::Px(0.0)),
-                        padding: UiRect::all(Val::Px(20.0)),
-                        margin: UiRect::all(Val::Px(20.0)),
-                        size: Size::new(Val::Auto, BILLBOARD_HEIGHT),
-                        ..default()
-                    },
+                    background_color: theme.billboard.background.into(),
+                    style: theme.root_style(),
                     ..default()
                 })
-                .insert(Root)
+                .insert((Root, Backdrop::default()))
                 .with_children(|parent| {
                     let name_tab = NodeBundle {
                         // N.b. systems should manage the visibility per passage
                         visibility: Visibility::INVISIBLE,
-                        background_color: BackgroundColor(billboard_color),
-                        style: Style {
-                            position_type: PositionType::Absolute,
-                            position: UiRect::bottom(BILLBOARD_HEIGHT),
-                            padding: UiRect::all(Val::Px(14.0)),
-                            border: UiRect::all(Val::Px(4.0)),
-                            ..default()
-                        },
+                        background_color: theme.name_tab_background().into(),
+                        style: theme.name_tab_style(),
                         ..default()
                     };
 
@@ -149,26 +264,46 @@ fn setup_billboard(mut commands: Commands, ass: Res<AssetServer>) {
                         .spawn(name_tab)
                         .with_children(|parent| {
                             parent
-                                .spawn(TextBundle::from_section("speaker name", style.clone()))
+                                .spawn(TextBundle::from_section(
+                                    "speaker name",
+                                    theme.fonts.name.text_style(&ass),
+                                ))
                                 .insert(SpeakerNameText);
                         })
                         .insert(SpeakerNameTab);
 
-                    let mut text = TextBundle::from_section("dialogue", style.clone());
+                    parent.spawn((
+                        ImageBundle {
+                            visibility: Visibility::INVISIBLE,
+                            style: theme.portrait_style(),
+                            ..default()
+                        },
+                        Portrait,
+                    ));
+
+                    let mut text =
+                        TextBundle::from_section("dialogue", theme.fonts.body.text_style(&ass));
                     text.style.position_type = PositionType::Absolute;
                     parent.spawn(text).insert(DialogueText);
                 });
         })
         .insert((
-            PlayHead {
-                head: 0,
-                secs_since_last_reveal: None,
-                fast_forward: false,
-                glyphs_per_sec: std::env::var("TALKIE_SPEED")
-                    .map(|s| s.parse().expect("invalid speed."))
-                    .unwrap_or(DEFAULT_GLYPHS_PER_SEC),
-            },
+            PlayHead::from_env(),
             Bookmark::default(),
-            Billboard { dialogue },
+            CurrentSpeaker::default(),
+            CurrentPassage::default(),
+            PendingCommands::default(),
+            CallStack::default(),
+            Goto(
+                event
+                    .section
+                    .map(|section| Jump::Goto(section.id.to_string())),
+            ),
+            DialoguePhase::Loading,
+            Modal,
+            Billboard {
+                dialogue,
+                theme: theme_handle,
+            },
         ));
 }
@@ -0,0 +1,324 @@
+//! Speech bubbles: small conversations that float above an entity in the
+//! world.
+//!
+//! Unlike the billboard, bubbles never take input focus. Each passage is
+//! revealed, held on screen for a moment, then replaced by the next until the
+//! section runs out. Any number of bubbles can be running at once, alongside
+//! the main conversation.
+
+use crate::plugin::billboard::{Billboard, Bookmark, CurrentPassage, PlayHead};
+use crate::plugin::memory::{DialogueMemory, DialogueVisits};
+use crate::plugin::theme::{TalkieTheme, DEFAULT_THEME_PATH};
+use crate::plugin::variables::DialogueVariables;
+use crate::plugin::{Dialogue, SectionId, TalkieRng};
+use crate::talkie_core::extract_commands;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy::ui::UiSystem;
+
+pub struct BubblePlugin;
+
+impl Plugin for BubblePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StartBubble>()
+            .add_system(start_bubbles)
+            .add_system(bubble_playback)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                position_bubbles
+                    .after(TransformSystem::TransformPropagate)
+                    .before(UiSystem::Flex),
+            );
+    }
+}
+
+/// How long a passage stays up once it has been fully revealed.
+const BUBBLE_HOLD_SECS: f32 = 2.5;
+
+const BUBBLE_MAX_WIDTH: f32 = 240.0;
+
+/// How far above the anchor's origin to draw the bubble, in world units.
+const BUBBLE_OFFSET: Vec3 = Vec3::new(0.0, 48.0, 0.0);
+
+/// Send this to show a section of dialogue in a bubble over an entity.
+///
+/// Starting a bubble on an entity that already has one replaces it.
+pub struct StartBubble {
+    /// The entity to follow. It needs a `GlobalTransform`.
+    pub anchor: Entity,
+    pub dialogue: Handle<Dialogue>,
+    /// The section to play, or the first section when `None`. It has to be
+    /// one from `dialogue`.
+    pub section: Option<SectionId>,
+}
+
+#[derive(Component)]
+pub struct SpeechBubble {
+    pub anchor: Entity,
+    source: BubbleSource,
+    started: bool,
+    hold: Timer,
+}
+
+/// What a bubble has to say.
+pub enum BubbleSource {
+    /// The section to play, or the first section when `None`.
+    Section(Option<SectionId>),
+    /// A single line, such as a bark.
+    Line(String),
+}
+
+#[derive(Component)]
+struct BubbleText;
+
+fn start_bubbles(
+    mut commands: Commands,
+    mut events: EventReader<StartBubble>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    existing: Query<(Entity, &SpeechBubble)>,
+) {
+    for event in events.iter() {
+        if let Some(section) = event.section {
+            if !section.is_in(&ass, &event.dialogue) {
+                eprintln!(
+                    "Can't show `{section}` in a speech bubble, since it's from {}.",
+                    section.path
+                );
+                continue;
+            }
+        }
+        spawn_bubble(
+            &mut commands,
+            &ass,
+            &themes,
+            &existing,
+            event.anchor,
+            event.dialogue.clone(),
+            BubbleSource::Section(event.section),
+        );
+    }
+}
+
+/// Show a bubble over `anchor`, replacing any it already has.
+pub fn spawn_bubble(
+    commands: &mut Commands,
+    ass: &AssetServer,
+    themes: &Assets<TalkieTheme>,
+    existing: &Query<(Entity, &SpeechBubble)>,
+    anchor: Entity,
+    dialogue: Handle<Dialogue>,
+    source: BubbleSource,
+) {
+    for (entity, bubble) in existing {
+        if bubble.anchor == anchor {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    let theme_handle = ass.load(DEFAULT_THEME_PATH);
+    let theme = themes.get(&theme_handle).cloned().unwrap_or_default();
+
+    commands
+        .spawn((
+            NodeBundle {
+                // Stays hidden until it has been placed over the anchor.
+                visibility: Visibility::INVISIBLE,
+                background_color: theme.billboard.background.into(),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    padding: UiRect::all(Val::Px(theme.name_tab.padding)),
+                    max_size: Size::new(Val::Px(BUBBLE_MAX_WIDTH), Val::Auto),
+                    ..default()
+                },
+                ..default()
+            },
+            SpeechBubble {
+                anchor,
+                source,
+                started: false,
+                hold: Timer::from_seconds(BUBBLE_HOLD_SECS, TimerMode::Once),
+            },
+            PlayHead::from_env(),
+            Bookmark::default(),
+            CurrentPassage::default(),
+            Billboard {
+                dialogue,
+                theme: theme_handle,
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", theme.fonts.body.text_style(ass)),
+                BubbleText,
+            ));
+        });
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn bubble_playback(
+    mut commands: Commands,
+    time: Res<Time>,
+    dialogue: Res<Assets<Dialogue>>,
+    mut memory: ResMut<DialogueMemory>,
+    mut rng: ResMut<TalkieRng>,
+    variables: Res<DialogueVariables>,
+    mut visits: ResMut<DialogueVisits>,
+    mut bubbles: Query<(
+        Entity,
+        &mut SpeechBubble,
+        &Billboard,
+        &mut PlayHead,
+        &mut Bookmark,
+        &mut CurrentPassage,
+        &Children,
+    )>,
+    mut texts: Query<&mut Text, With<BubbleText>>,
+) {
+    for (entity, mut bubble, billboard, mut playhead, mut bookmark, mut current, children) in
+        &mut bubbles
+    {
+        let Some(dialogue) = dialogue.get(&billboard.dialogue) else {
+            continue;
+        };
+
+        let SpeechBubble {
+            source,
+            started,
+            hold,
+            ..
+        } = &mut *bubble;
+        let current = &mut *current;
+
+        let entire_text = match source {
+            BubbleSource::Line(line) => {
+                if !current.resolved {
+                    *current = CurrentPassage {
+                        glyphs: line.chars().count(),
+                        text: line.clone(),
+                        resolved: true,
+                        ..default()
+                    };
+                }
+                (bookmark.passage == 0).then_some(current.text.as_str())
+            }
+            BubbleSource::Section(section) => {
+                if !*started {
+                    *started = true;
+                    if dialogue.dialogue.passage_groups.is_empty() {
+                        eprintln!("`{}` has no sections for speech bubble.", dialogue.path);
+                        commands.entity(entity).despawn_recursive();
+                        continue;
+                    }
+                    if let Some(id) = section {
+                        match dialogue.dialogue.section_index(id.id) {
+                            Some(idx) => {
+                                bookmark.passage_group = idx;
+                                visits.visit(dialogue, id.id);
+                            }
+                            None => {
+                                eprintln!("No section `{id}` for speech bubble.");
+                                commands.entity(entity).despawn_recursive();
+                                continue;
+                            }
+                        }
+                    }
+                }
+                let group = &dialogue.dialogue.passage_groups[bookmark.passage_group];
+                let allowed = match &group.condition {
+                    Some(condition) => variables.test(condition, dialogue, &visits),
+                    None => true,
+                };
+                if allowed && bookmark.passage < group.visit_len() {
+                    if !current.resolved {
+                        let (index, text) = memory.resolve_passage(
+                            billboard.dialogue.id(),
+                            bookmark.passage_group,
+                            group,
+                            bookmark.passage,
+                            &mut rng.0,
+                        );
+                        let text = variables.interpolate(&text, dialogue, &visits);
+                        // Bubbles don't run commands, but the markers still
+                        // shouldn't show.
+                        let (text, _) = extract_commands(&text).unwrap_or_else(|_| (text, vec![]));
+                        *current = CurrentPassage {
+                            index,
+                            glyphs: text.chars().count(),
+                            text,
+                            // Still covering the last passage, so the text
+                            // starts over on the next reveal.
+                            revealed: current.revealed,
+                            resolved: true,
+                            ..default()
+                        };
+                    }
+                    Some(current.text.as_str())
+                } else {
+                    None
+                }
+            }
+        };
+        let Some(entire_text) = entire_text else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        if playhead.head < current.glyphs {
+            let since = playhead.secs_since_last_reveal.unwrap_or_default() + time.delta_seconds();
+            let (reveal_how_many, remainder) =
+                crate::talkie_core::calc_glyphs_to_reveal(since, playhead.glyphs_per_sec);
+            playhead.secs_since_last_reveal = Some(remainder);
+            playhead.head += reveal_how_many;
+
+            if current.revealed.is_behind(playhead.head) {
+                for child in children {
+                    if let Ok(mut text) = texts.get_mut(*child) {
+                        let shown = &mut text.sections[0].value;
+                        current.revealed.catch_up(entire_text, playhead.head, shown);
+                    }
+                }
+            }
+        } else if hold.tick(time.delta()).finished() {
+            hold.reset();
+            playhead.head = 0;
+            playhead.secs_since_last_reveal = None;
+            bookmark.passage += 1;
+            current.resolved = false;
+        }
+    }
+}
+
+/// Keep each bubble hovering over its anchor, as seen by the camera.
+fn position_bubbles(
+    mut commands: Commands,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    anchors: Query<&GlobalTransform>,
+    mut bubbles: Query<(Entity, &SpeechBubble, &Node, &mut Style, &mut Visibility)>,
+) {
+    let Some((camera, camera_transform)) = cameras.iter().find(|(camera, _)| camera.is_active)
+    else {
+        return;
+    };
+
+    for (entity, bubble, node, mut style, mut visibility) in &mut bubbles {
+        let Ok(anchor) = anchors.get(bubble.anchor) else {
+            // The speaker is gone, so the bubble goes with it.
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        match camera.world_to_viewport(camera_transform, anchor.translation() + BUBBLE_OFFSET) {
+            Some(pos) => {
+                // Centered over the anchor, with the bottom edge at the offset.
+                style.position = UiRect {
+                    left: Val::Px(pos.x - node.size().x / 2.0),
+                    bottom: Val::Px(pos.y),
+                    ..default()
+                };
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Rolling the skill checks that choices can carry.
+
+use crate::talkie_core::Check;
+use bevy::prelude::*;
+use rand::Rng;
+
+/// Supplies the player's stats for skill checks.
+///
+/// Closures taking the name of the stat work as providers too.
+pub trait StatProvider: Send + Sync + 'static {
+    fn stat(&self, name: &str) -> Option<i32>;
+}
+
+impl<F> StatProvider for F
+where
+    F: Fn(&str) -> Option<i32> + Send + Sync + 'static,
+{
+    fn stat(&self, name: &str) -> Option<i32> {
+        self(name)
+    }
+}
+
+/// How skill checks get rolled.
+///
+/// Stats the provider doesn't know about count as zero. Setting `forced`
+/// skips the dice entirely, which is handy for testing either branch. It
+/// starts out from the `TALKIE_FORCE_CHECKS` env var (`pass` or `fail`).
+#[derive(Resource)]
+pub struct SkillChecks {
+    provider: Option<Box<dyn StatProvider>>,
+    pub forced: Option<bool>,
+}
+
+impl Default for SkillChecks {
+    fn default() -> Self {
+        let forced = match std::env::var("TALKIE_FORCE_CHECKS").as_deref() {
+            Ok("pass") => Some(true),
+            Ok("fail") => Some(false),
+            _ => None,
+        };
+        SkillChecks {
+            provider: None,
+            forced,
+        }
+    }
+}
+
+impl SkillChecks {
+    pub fn set_provider(&mut self, provider: impl StatProvider) {
+        self.provider = Some(Box::new(provider));
+    }
+
+    pub fn stat(&self, name: &str) -> i32 {
+        self.provider
+            .as_ref()
+            .and_then(|provider| provider.stat(name))
+            .unwrap_or_default()
+    }
+
+    /// The chance of passing, from 0 to 1.
+    pub fn odds(&self, check: &Check) -> f32 {
+        match self.forced {
+            Some(passed) => f32::from(u8::from(passed)),
+            None => check.odds(self.stat(&check.stat)),
+        }
+    }
+
+    /// Whether the check passes this time.
+    pub fn roll(&self, check: &Check, rng: &mut impl Rng) -> bool {
+        match self.forced {
+            Some(passed) => passed,
+            None => check.roll(self.stat(&check.stat), rng).1,
+        }
+    }
+}
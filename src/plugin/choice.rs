@@ -1,6 +1,14 @@
-use crate::plugin::billboard::PlayHead;
+use crate::plugin::backlog::{BacklogView, DialogueLog, LogEntry};
+use crate::plugin::billboard::{Billboard, CurrentSpeaker, Modal, SpokenLine};
+use crate::plugin::check::SkillChecks;
+use crate::plugin::command::not_waiting;
 use crate::plugin::goto::Goto;
-use crate::plugin::{despawn_with, Action, GameState};
+use crate::plugin::memory::{ChoiceKey, DialogueMemory, DialogueVisits};
+use crate::plugin::phase::{in_phase, ApplyNextPhase, DialoguePhase, NextPhase, PhaseChanged};
+use crate::plugin::theme::TalkieTheme;
+use crate::plugin::{Action, Dialogue, TalkieRng};
+use crate::talkie_core::{visible_choices, Condition, PassageGroup};
+use bevy::asset::HandleId;
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 use leafwing_input_manager::action_state::ActionState;
@@ -9,13 +17,15 @@ pub struct ChoicePlugin;
 
 impl Plugin for ChoicePlugin {
     fn build(&self, app: &mut App) {
-        app.add_enter_system(GameState::Choice, setup_choices)
-            .add_exit_system(GameState::Choice, teardown_choices)
-            .add_exit_system(GameState::Choice, despawn_with::<ChoiceList>)
+        app.add_system_to_stage(CoreStage::PreUpdate, setup_choices.after(ApplyNextPhase))
+            .add_system_to_stage(CoreStage::PreUpdate, teardown_choices.after(ApplyNextPhase))
             .add_system_set(
                 ConditionSet::new()
-                    .run_in_state(GameState::Choice)
+                    .run_if(in_phase(DialoguePhase::Choice))
+                    .run_if(not_waiting)
+                    .run_unless_resource_exists::<BacklogView>()
                     .with_system(choice_cursor_system)
+                    .with_system(countdown_bar_system)
                     .with_system(handle_choice_input)
                     .into(),
             );
@@ -35,24 +45,79 @@ fn choice_cursor_system(
     ));
 }
 
+fn countdown_bar_system(
+    choice_list: Query<&ChoiceList>,
+    mut countdown_bar: Query<&mut Style, With<CountdownBar>>,
+) {
+    let choice_list = choice_list.single();
+    if let (Some(timer), Ok(mut bar_style)) = (&choice_list.timer, countdown_bar.get_single_mut()) {
+        bar_style.size.width = Val::Percent(timer.percent_left() * 100.0);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_choice_input(
     mut commands: Commands,
+    time: Res<Time>,
     mut choice_list: Query<&mut ChoiceList>,
-    mut goto: ResMut<Goto>,
-    query: Query<&ActionState<Action>, With<PlayHead>>,
+    mut log: ResMut<DialogueLog>,
+    mut memory: ResMut<DialogueMemory>,
+    mut rng: ResMut<TalkieRng>,
+    checks: Res<SkillChecks>,
+    dialogues: Res<Assets<Dialogue>>,
+    mut visits: ResMut<DialogueVisits>,
+    mut query: Query<(Entity, &ActionState<Action>, &mut Goto, &Billboard), With<Modal>>,
 ) {
-    let action_state = query.single();
-
-    if action_state.just_pressed(Action::Confirm) {
-        let choice_list = choice_list.single();
-        goto.0 = choice_list.choices[choice_list.selected_choice]
-            .goto
-            .clone();
-        commands.insert_resource(NextState(GameState::Goto));
+    let (conversation, action_state, mut goto, billboard) = query.single_mut();
+    let mut choice_list = choice_list.single_mut();
+
+    // When time runs out, the default is picked regardless of where the cursor
+    // is.
+    let timed_out = match choice_list.timer.as_mut() {
+        Some(timer) => timer.tick(time.delta()).finished(),
+        None => false,
+    };
+    if timed_out {
+        choice_list.selected_choice = choice_list.default;
+    }
+
+    if timed_out || action_state.just_pressed(Action::Confirm) {
+        let entry = &choice_list.choices[choice_list.selected_choice];
+        memory.taken_choices.insert(entry.key);
+        if let (Some(name), Some(dialogue)) =
+            (&entry.visit_name, dialogues.get(&billboard.dialogue))
+        {
+            visits.visit(dialogue, name.clone());
+        }
+        log.0.push(LogEntry::Choice {
+            label: entry.choice.label.clone(),
+        });
+        let jump = match &entry.choice.check {
+            Some(check) => entry
+                .choice
+                .jump_after_check(checks.roll(check, &mut rng.0)),
+            None => entry.choice.jump(),
+        };
+        // Choices that don't lead anywhere in particular follow the section.
+        if let Some(jump) = jump {
+            goto.0 = Some(jump);
+        }
+        match &entry.choice.say {
+            // Say the line in full before going anywhere.
+            Some(text) => commands.entity(conversation).insert((
+                SpokenLine {
+                    speaker: entry.choice.speaker.clone(),
+                    text: text.clone(),
+                },
+                NextPhase(DialoguePhase::Playback),
+            )),
+            None => commands
+                .entity(conversation)
+                .insert(NextPhase(DialoguePhase::Goto)),
+        };
         return;
     }
 
-    let mut choice_list = choice_list.single_mut();
     if action_state.just_pressed(Action::Up) && choice_list.selected_choice > 0 {
         choice_list.selected_choice -= 1;
     }
@@ -63,45 +128,88 @@ fn handle_choice_input(
     }
 }
 
-fn setup_choices(mut commands: Commands, choices: Res<Choices>, ass: Res<AssetServer>) {
-    let style = TextStyle {
-        font: ass.load("Sansation-Regular.ttf"),
-        font_size: 20.0,
-        color: Color::WHITE,
-    };
+fn setup_choices(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    conversations: Query<(&Choices, &Billboard, &CurrentSpeaker)>,
+) {
+    for event in events.iter() {
+        if !event.entered(DialoguePhase::Choice) {
+            continue;
+        }
+        if let Ok((choices, billboard, speaker)) = conversations.get(event.conversation) {
+            let menu = spawn_menu(&mut commands, &ass, &themes, choices, billboard, speaker);
+            commands.entity(event.conversation).add_child(menu);
+        }
+    }
+}
+
+fn spawn_menu(
+    commands: &mut Commands,
+    ass: &AssetServer,
+    themes: &Assets<TalkieTheme>,
+    choices: &Choices,
+    billboard: &Billboard,
+    speaker: &CurrentSpeaker,
+) -> Entity {
+    let theme = TalkieTheme::current(themes, billboard, speaker);
+    let style = theme.fonts.choices.text_style(ass);
+    let margin = Val::Px(theme.billboard.margin);
 
-    let menu = commands
+    commands
         .spawn(NodeBundle {
             style: Style {
                 size: Size::new(Val::Percent(100.), Val::Percent(100.)),
-                position: UiRect::new(Val::Px(20.0), Val::Px(20.0), Val::Auto, Val::Px(20.0)),
+                position: UiRect::new(margin, margin, Val::Auto, margin),
                 position_type: PositionType::Absolute,
-                padding: UiRect::all(Val::Px(20.0)),
+                padding: UiRect::all(Val::Px(theme.billboard.padding)),
                 ..default()
             },
             ..default()
         })
         .with_children(|parent| {
             parent.spawn((
-                NodeBundle {
-                    style: Style {
-                        size: Size::new(Val::Px(BTN_HEIGHT), Val::Px(BTN_HEIGHT)),
+                theme.cursor_bundle(
+                    ass,
+                    Style {
                         position_type: PositionType::Absolute,
                         ..default()
                     },
-                    background_color: Color::rgb(0.9, 0.3, 0.3).into(),
-                    ..default()
-                },
+                ),
                 ChoiceCursor,
             ));
 
-            let choice_count = choices.0.len();
-            for (idx, choice) in choices.0.iter().enumerate() {
+            let choice_count = choices.choices.len();
+            if choices.timeout.is_some() {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(100.), Val::Px(COUNTDOWN_HEIGHT)),
+                            position_type: PositionType::Absolute,
+                            position: UiRect::bottom(Val::Px(
+                                (choice_count + 1) as f32 * (BTN_HEIGHT + GUTTER_V),
+                            )),
+                            ..default()
+                        },
+                        background_color: theme.choices.countdown_color.into(),
+                        ..default()
+                    },
+                    CountdownBar,
+                ));
+            }
+
+            for (idx, entry) in choices.choices.iter().enumerate() {
                 // We need to calculate the offset from the end of the list in order
                 // to get these positioned correctly when pinned to the bottom.
                 let v_offset = choice_count - idx;
+                let mut style = style.clone();
+                if entry.taken {
+                    style.color = theme.choices.taken_color.into();
+                }
                 // FIXME: might need to wrap each in a node to give some padding
-                let mut txt = TextBundle::from_section(&choice.label, style.clone());
+                let mut txt = TextBundle::from_section(&entry.choice.label, style);
                 txt.style.position_type = PositionType::Absolute;
                 txt.style.position =
                     UiRect::bottom(Val::Px(v_offset as f32 * (BTN_HEIGHT + GUTTER_V)));
@@ -109,30 +217,137 @@ fn setup_choices(mut commands: Commands, choices: Res<Choices>, ass: Res<AssetSe
             }
         })
         .insert(ChoiceList {
-            selected_choice: 0,
-            choices: choices.0.clone(),
+            selected_choice: choices.default,
+            choices: choices.choices.clone(),
+            default: choices.default,
+            timer: choices
+                .timeout
+                .map(|secs| Timer::from_seconds(secs, TimerMode::Once)),
         })
-        .id();
+        .id()
+}
 
-    commands.entity(menu);
+fn teardown_choices(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    menus: Query<(Entity, &Parent), With<ChoiceList>>,
+) {
+    for event in events.iter() {
+        if !event.exited(DialoguePhase::Choice) {
+            continue;
+        }
+        commands.entity(event.conversation).remove::<Choices>();
+        for (menu, parent) in &menus {
+            if parent.get() == event.conversation {
+                commands.entity(menu).despawn_recursive();
+            }
+        }
+    }
 }
 
-// XXX: Might not be needed if we can cleanup in the `choice_system`
-fn teardown_choices(mut commands: Commands) {
-    commands.remove_resource::<Choices>();
+/// Component used to build a menu of choices, added to a conversation before
+/// it moves into the choice phase.
+#[derive(Component)]
+pub struct Choices {
+    pub choices: Vec<MenuChoice>,
+    /// Seconds until the default choice is picked automatically.
+    pub timeout: Option<f32>,
+    /// Index of the choice to start on, and to pick when time runs out.
+    pub default: usize,
 }
 
-/// Resource used to build a menu of choices.
-#[derive(Resource)]
-pub struct Choices(pub Vec<crate::talkie_core::Choice>);
+impl Choices {
+    /// Build the menu for the given passage group, leaving out any choices
+    /// that shouldn't be offered given what the player has picked before, or
+    /// whose conditions don't hold.
+    ///
+    /// Returns `None` when there's nothing to pick from.
+    pub fn for_section(
+        dialogue: HandleId,
+        section: usize,
+        group: &PassageGroup,
+        memory: &DialogueMemory,
+        is_allowed: impl Fn(&Condition) -> bool,
+    ) -> Option<Self> {
+        let choices = group.choices.as_deref()?;
+        let key = |choice| ChoiceKey {
+            dialogue,
+            section,
+            choice,
+        };
+
+        let visible = visible_choices(
+            choices,
+            |idx| memory.is_taken(&key(idx)),
+            |idx| match &choices[idx].condition {
+                Some(condition) => is_allowed(condition),
+                None => true,
+            },
+        );
+        if visible.is_empty() {
+            return None;
+        }
+
+        // The default is given as an index into the full list, so it needs to
+        // be found again amongst the choices that are left. If it's been left
+        // out, picking some other choice for the player would be a guess, so
+        // they're left to pick one themselves.
+        let mut timeout = group.timeout;
+        let default = match group.default {
+            Some(default) => match visible.iter().position(|&idx| idx == default) {
+                Some(position) => position,
+                None => {
+                    if timeout.take().is_some() {
+                        eprintln!(
+                            "Default choice {default} of section {section} isn't on offer, so nothing will be picked when time runs out."
+                        );
+                    }
+                    0
+                }
+            },
+            None => 0,
+        };
+
+        Some(Choices {
+            choices: visible
+                .into_iter()
+                .map(|idx| MenuChoice {
+                    choice: choices[idx].clone(),
+                    key: key(idx),
+                    taken: memory.is_taken(&key(idx)),
+                    visit_name: group.choice_visit_name(idx),
+                })
+                .collect(),
+            timeout,
+            default,
+        })
+    }
+}
+
+/// A single entry in the menu.
+#[derive(Debug, Clone)]
+pub struct MenuChoice {
+    pub choice: crate::talkie_core::Choice,
+    pub key: ChoiceKey,
+    /// Whether the player has picked this before.
+    pub taken: bool,
+    /// What picking it counts as a visit to, if anything.
+    pub visit_name: Option<String>,
+}
 
 #[derive(Component)]
 struct ChoiceCursor;
 
+/// Shrinks as the time left to pick a choice runs out.
+#[derive(Component)]
+struct CountdownBar;
+
 #[derive(Component)]
 struct ChoiceList {
     selected_choice: usize,
-    choices: Vec<crate::talkie_core::Choice>,
+    choices: Vec<MenuChoice>,
+    default: usize,
+    timer: Option<Timer>,
 }
 
 #[derive(Component, Debug)]
@@ -141,3 +356,5 @@ struct Choice;
 pub const BTN_HEIGHT: f32 = 28.;
 
 const GUTTER_V: f32 = 4.;
+
+const COUNTDOWN_HEIGHT: f32 = 6.;
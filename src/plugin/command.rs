@@ -0,0 +1,130 @@
+//! Running the commands dialogue asks for, either by way of `DialogueCommand`
+//! events or through handlers registered on `TalkiePlugin`.
+
+use crate::plugin::billboard::Modal;
+use crate::talkie_core::Command;
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::sync::Arc;
+
+pub struct CommandPlugin {
+    pub handlers: CommandHandlers,
+}
+
+impl Plugin for CommandPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DialogueCommand>()
+            .add_event::<CommandFinished>()
+            .init_resource::<NextCommandId>()
+            .insert_resource(self.handlers.clone())
+            .add_system_to_stage(CoreStage::PostUpdate, run_command_handlers)
+            .add_system(finish_commands)
+            .add_system(debug_commands);
+    }
+}
+
+/// Identifies a command the dialogue is waiting on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommandId(u64);
+
+#[derive(Resource, Default)]
+pub struct NextCommandId(u64);
+
+/// Sent for every command the dialogue runs.
+#[derive(Debug, Clone)]
+pub struct DialogueCommand {
+    /// The conversation (or speech bubble) the command came from.
+    pub conversation: Entity,
+    pub name: String,
+    pub args: Vec<String>,
+    /// When set, the conversation is paused until a `CommandFinished` is sent
+    /// back with this id.
+    pub wait: Option<CommandId>,
+}
+
+/// Send this once the game is done with a command that was awaited.
+pub struct CommandFinished(pub CommandId);
+
+/// The commands a conversation is waiting on before it carries on.
+#[derive(Component, Debug, Default)]
+pub struct PendingCommands(Vec<CommandId>);
+
+impl PendingCommands {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+pub type CommandHandler = Arc<dyn Fn(&mut World, &DialogueCommand) + Send + Sync>;
+
+/// Functions to call for commands with a given name.
+#[derive(Resource, Default, Clone)]
+pub struct CommandHandlers(pub HashMap<String, CommandHandler>);
+
+/// Hands out commands to the game.
+#[derive(SystemParam)]
+pub struct CommandSender<'w, 's> {
+    events: EventWriter<'w, 's, DialogueCommand>,
+    next_id: ResMut<'w, NextCommandId>,
+}
+
+impl<'w, 's> CommandSender<'w, 's> {
+    pub fn send(&mut self, conversation: Entity, command: &Command, pending: &mut PendingCommands) {
+        let wait = command.wait.then(|| {
+            self.next_id.0 += 1;
+            let id = CommandId(self.next_id.0);
+            pending.0.push(id);
+            id
+        });
+        self.events.send(DialogueCommand {
+            conversation,
+            name: command.name.clone(),
+            args: command.args.clone(),
+            wait,
+        });
+    }
+}
+
+/// Run condition: the main conversation isn't waiting on any commands.
+pub fn not_waiting(query: Query<&PendingCommands, With<Modal>>) -> bool {
+    query.iter().all(|pending| pending.0.is_empty())
+}
+
+fn finish_commands(
+    mut events: EventReader<CommandFinished>,
+    mut query: Query<&mut PendingCommands>,
+) {
+    for CommandFinished(id) in events.iter() {
+        for mut pending in &mut query {
+            pending.0.retain(|x| x != id);
+        }
+    }
+}
+
+fn debug_commands(mut events: EventReader<DialogueCommand>) {
+    for event in events.iter() {
+        println!(
+            "Got command {} {:?} from {:?}",
+            event.name, event.args, event.conversation
+        );
+    }
+}
+
+fn run_command_handlers(world: &mut World, mut reader: Local<ManualEventReader<DialogueCommand>>) {
+    let commands: Vec<DialogueCommand> = reader
+        .iter(world.resource::<Events<DialogueCommand>>())
+        .cloned()
+        .collect();
+    for command in commands {
+        let handler = world
+            .resource::<CommandHandlers>()
+            .0
+            .get(&command.name)
+            .cloned();
+        if let Some(handler) = handler {
+            handler(world, &command);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Serving assets from inside the binary, for builds that ship as one file.
+//!
+//! The build script picks out the files to embed: everything under
+//! `assets/dialogue`, the voice clips the dialogue uses, every theme, and the
+//! fonts and images those themes use.
+//! Anything else is still read from disk (or fetched, on the web) as usual.
+
+use bevy::{
+    asset::{AssetIo, AssetIoError, FileType, Metadata},
+    prelude::*,
+    utils::{BoxedFuture, HashMap, HashSet},
+};
+use std::path::{Path, PathBuf};
+
+/// Every embedded file, by its path under `assets`.
+const FILES: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// Serves the embedded assets through the `AssetServer`.
+///
+/// Has to be added before bevy's `AssetPlugin`, which otherwise sets up a
+/// server that only reads from disk:
+///
+/// ```ignore
+/// app.add_plugins(DefaultPlugins.build().add_before::<AssetPlugin, _>(EmbeddedAssetsPlugin));
+/// ```
+pub struct EmbeddedAssetsPlugin;
+
+impl Plugin for EmbeddedAssetsPlugin {
+    fn build(&self, app: &mut App) {
+        // Embedded files never change, so there's nothing worth watching.
+        let fallback = AssetPlugin::default().create_platform_default_asset_io();
+        app.insert_resource(AssetServer::new(EmbeddedAssetIo::new(fallback)));
+    }
+}
+
+struct EmbeddedAssetIo {
+    files: HashMap<&'static Path, &'static [u8]>,
+    /// Every directory with an embedded file somewhere under it.
+    dirs: HashSet<&'static Path>,
+    /// Where everything that wasn't embedded comes from.
+    fallback: Box<dyn AssetIo>,
+}
+
+impl EmbeddedAssetIo {
+    fn new(fallback: Box<dyn AssetIo>) -> Self {
+        let files: HashMap<_, _> = FILES
+            .iter()
+            .map(|&(path, bytes)| (Path::new(path), bytes))
+            .collect();
+        let dirs = files
+            .keys()
+            .flat_map(|path| path.ancestors().skip(1))
+            .collect();
+        EmbeddedAssetIo {
+            files,
+            dirs,
+            fallback,
+        }
+    }
+}
+
+impl AssetIo for EmbeddedAssetIo {
+    fn load_path<'a>(&'a self, path: &'a Path) -> BoxedFuture<'a, Result<Vec<u8>, AssetIoError>> {
+        match self.files.get(path) {
+            Some(bytes) => Box::pin(async move { Ok(bytes.to_vec()) }),
+            None => self.fallback.load_path(path),
+        }
+    }
+
+    fn read_directory(
+        &self,
+        path: &Path,
+    ) -> Result<Box<dyn Iterator<Item = PathBuf>>, AssetIoError> {
+        if !self.dirs.contains(path) {
+            return self.fallback.read_directory(path);
+        }
+        let children: HashSet<PathBuf> = self
+            .files
+            .keys()
+            .chain(&self.dirs)
+            .filter(|child| child.parent() == Some(path))
+            .map(|child| child.to_path_buf())
+            .collect();
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn get_metadata(&self, path: &Path) -> Result<Metadata, AssetIoError> {
+        if self.files.contains_key(path) {
+            Ok(Metadata::new(FileType::File))
+        } else if self.dirs.contains(path) {
+            Ok(Metadata::new(FileType::Directory))
+        } else {
+            self.fallback.get_metadata(path)
+        }
+    }
+
+    fn watch_path_for_changes(&self, path: &Path) -> Result<(), AssetIoError> {
+        if self.files.contains_key(path) {
+            return Ok(());
+        }
+        self.fallback.watch_path_for_changes(path)
+    }
+
+    fn watch_for_changes(&self) -> Result<(), AssetIoError> {
+        self.fallback.watch_for_changes()
+    }
+}
@@ -1,5 +1,7 @@
-use crate::plugin::billboard::{Billboard, Bookmark};
-use crate::plugin::{Dialogue, GameState};
+use crate::plugin::billboard::{Billboard, Bookmark, Modal};
+use crate::plugin::phase::{in_phase, DialoguePhase, NextPhase};
+use crate::plugin::Dialogue;
+use crate::talkie_core::{split_target, Jump};
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 
@@ -7,41 +9,102 @@ pub struct GotoPlugin;
 
 impl Plugin for GotoPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            ConditionSet::new()
-                .run_in_state(GameState::Goto)
-                .with_system(goto_system)
-                .into(),
-        );
+        app.add_system(goto_system.run_if(in_phase(DialoguePhase::Goto)));
     }
 }
 
 fn goto_system(
     mut commands: Commands,
-    mut goto: ResMut<Goto>,
-    mut bookmark: Query<&mut Bookmark>,
-    billboard: Query<&Billboard>,
-    dialogue: Res<Assets<Dialogue>>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Goto,
+            &mut CallStack,
+            &mut Bookmark,
+            &mut Billboard,
+        ),
+        With<Modal>,
+    >,
+    dialogues: Res<Assets<Dialogue>>,
 ) {
-    if let Some(passage_group_id) = goto.0.take() {
-        let mut bookmark = bookmark.single_mut();
-        println!("Got goto={passage_group_id}");
-
-        let billboard = billboard.single();
-        let dialogue = dialogue.get(&billboard.dialogue).expect("dialogue");
-
-        bookmark.passage_group = dialogue
-            .0
-            .passage_groups
-            .iter()
-            .position(|group| group.id.as_ref() == Some(&passage_group_id))
-            .unwrap();
+    let (conversation, mut goto, mut stack, mut bookmark, mut billboard) = query.single_mut();
+
+    match goto.0.take() {
+        Some(Jump::Goto(target)) => {
+            println!("Got goto={target}");
+            jump(&dialogues, &mut billboard, &mut bookmark, &target);
+        }
+        Some(Jump::Call(target)) => {
+            println!("Got call={target}");
+            // By now the bookmark has already moved on to the section after
+            // the caller, which is where a return should pick up.
+            stack
+                .0
+                .push((billboard.dialogue.clone(), bookmark.passage_group));
+            jump(&dialogues, &mut billboard, &mut bookmark, &target);
+        }
+        Some(Jump::Return) => match stack.0.pop() {
+            Some((dialogue, passage_group)) => {
+                println!("Got return");
+                billboard.dialogue = dialogue;
+                bookmark.passage_group = passage_group;
+            }
+            None => println!("Got return with nothing to return to"),
+        },
+        Some(Jump::End) => {
+            println!("Got end");
+            commands.entity(conversation).despawn_recursive();
+            return;
+        }
+        None => println!("Got goto=Next"),
+    }
+    commands
+        .entity(conversation)
+        .insert(NextPhase(DialoguePhase::Playback));
+}
+
+/// Move the bookmark to the section a `goto` or `call` points at, switching
+/// over to another dialogue file if need be.
+///
+/// If the section can't be found, the bookmark is left where it is.
+fn jump(
+    dialogues: &Assets<Dialogue>,
+    billboard: &mut Billboard,
+    bookmark: &mut Bookmark,
+    target: &str,
+) {
+    let (module, id) = split_target(target);
+    let handle = match module {
+        Some(module) => {
+            let current = dialogues.get(&billboard.dialogue).expect("dialogue");
+            current.modules.get(module).cloned()
+        }
+        None => Some(billboard.dialogue.clone()),
+    };
+    let Some((handle, dialogue)) = handle.and_then(|h| Some((h.clone(), dialogues.get(&h)?)))
+    else {
+        eprintln!("Dialogue for `{target}` isn't loaded.");
+        return;
+    };
+
+    let section = if id.is_empty() {
+        (!dialogue.dialogue.passage_groups.is_empty()).then_some(0)
     } else {
-        println!("Got goto=Next");
+        dialogue.dialogue.section_index(id)
+    };
+    match section {
+        Some(passage_group) => {
+            billboard.dialogue = handle;
+            bookmark.passage_group = passage_group;
+        }
+        None => eprintln!("No section for `{target}`."),
     }
-    commands.insert_resource(NextState(GameState::Playback));
 }
 
-/// Resource used to signal a jump to a given passage group.
-#[derive(Resource)]
-pub struct Goto(pub Option<String>);
+/// Component used to signal a jump to a given passage group.
+#[derive(Component)]
+pub struct Goto(pub Option<Jump>);
+
+/// Where to pick up again after each `call` returns, most recent last.
+#[derive(Component, Debug, Default)]
+pub struct CallStack(pub Vec<(Handle<Dialogue>, usize)>);
@@ -0,0 +1,267 @@
+//! Player controls.
+//!
+//! Every action has a set of default bindings for keyboard and gamepad. Players
+//! can remap any of them by providing a bindings file, which is read once at
+//! startup (see `load_input_map()`).
+
+use crate::plugin::Action;
+use anyhow::Result;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::Deserialize;
+
+/// Where to look for the bindings file when the `TALKIE_BINDINGS` env var is
+/// not set.
+const DEFAULT_BINDINGS_PATH: &str = "assets/bindings.toml";
+
+/// How far a stick needs to be pushed before it counts as a button press.
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// The contents of the bindings file, with a table per action.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BindingsFile {
+    confirm: Option<ActionBindings>,
+    up: Option<ActionBindings>,
+    down: Option<ActionBindings>,
+    skip: Option<ActionBindings>,
+    backlog: Option<ActionBindings>,
+    cancel: Option<ActionBindings>,
+}
+
+impl BindingsFile {
+    fn actions(self) -> impl Iterator<Item = (Action, ActionBindings)> {
+        [
+            (Action::Confirm, self.confirm),
+            (Action::Up, self.up),
+            (Action::Down, self.down),
+            (Action::Skip, self.skip),
+            (Action::Backlog, self.backlog),
+            (Action::Cancel, self.cancel),
+        ]
+        .into_iter()
+        .filter_map(|(action, bindings)| Some((action, bindings?)))
+    }
+}
+
+/// The inputs for a single action, as listed in the bindings file.
+///
+/// ```toml
+/// [confirm]
+/// keys = ["Space", "Return"]
+/// buttons = ["South"]
+///
+/// [up]
+/// keys = ["Up", "W"]
+/// buttons = ["DPadUp"]
+/// axes = [{ axis = "LeftStickY", threshold = 0.5 }]
+/// ```
+///
+/// Names match bevy's `KeyCode`, `GamepadButtonType`, and `GamepadAxisType`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ActionBindings {
+    keys: Vec<KeyCode>,
+    buttons: Vec<GamepadButtonType>,
+    axes: Vec<AxisBinding>,
+}
+
+/// A stick or trigger, which activates the action once pushed past the
+/// threshold.
+///
+/// The sign of the threshold gives the direction: a negative threshold means
+/// the action fires when the axis is pushed below it.
+#[derive(Debug, Deserialize)]
+struct AxisBinding {
+    axis: GamepadAxisType,
+    threshold: f32,
+}
+
+impl From<&AxisBinding> for SingleAxis {
+    fn from(binding: &AxisBinding) -> Self {
+        if binding.threshold < 0.0 {
+            SingleAxis::negative_only(binding.axis, binding.threshold)
+        } else {
+            SingleAxis::positive_only(binding.axis, binding.threshold)
+        }
+    }
+}
+
+/// The bindings shipped with the game.
+pub fn default_input_map() -> InputMap<Action> {
+    let mut input_map = InputMap::new([
+        (KeyCode::Space, Action::Confirm),
+        (KeyCode::Return, Action::Confirm),
+        (KeyCode::Up, Action::Up),
+        (KeyCode::W, Action::Up),
+        (KeyCode::Down, Action::Down),
+        (KeyCode::S, Action::Down),
+        (KeyCode::Tab, Action::Skip),
+        (KeyCode::L, Action::Backlog),
+        (KeyCode::Escape, Action::Cancel),
+        (KeyCode::Back, Action::Cancel),
+    ]);
+    input_map
+        .insert_multiple([
+            (GamepadButtonType::South, Action::Confirm),
+            (GamepadButtonType::DPadUp, Action::Up),
+            (GamepadButtonType::DPadDown, Action::Down),
+            (GamepadButtonType::West, Action::Skip),
+            (GamepadButtonType::North, Action::Backlog),
+            (GamepadButtonType::East, Action::Cancel),
+        ])
+        .insert_multiple([
+            (
+                SingleAxis::positive_only(GamepadAxisType::LeftStickY, STICK_THRESHOLD),
+                Action::Up,
+            ),
+            (
+                SingleAxis::negative_only(GamepadAxisType::LeftStickY, -STICK_THRESHOLD),
+                Action::Down,
+            ),
+        ]);
+    input_map
+}
+
+/// Build the input map, applying any overrides from the bindings file.
+///
+/// The file is read from the path in the `TALKIE_BINDINGS` env var, falling
+/// back to `assets/bindings.toml`. Actions listed in the file have their
+/// default bindings replaced; actions that are not listed keep the defaults.
+///
+/// A missing file is not an error, but a file that can't be parsed is reported
+/// and ignored.
+pub fn load_input_map() -> InputMap<Action> {
+    let path = std::env::var("TALKIE_BINDINGS").unwrap_or_else(|_| DEFAULT_BINDINGS_PATH.into());
+    // N.b. there's no filesystem to read from on wasm so this will always fall
+    // through to the defaults there.
+    let Ok(bytes) = std::fs::read(&path) else {
+        return default_input_map();
+    };
+
+    input_map_from(&bytes).unwrap_or_else(|err| {
+        eprintln!("Ignoring bindings file `{path}`: {err}");
+        default_input_map()
+    })
+}
+
+/// The default bindings, with the actions listed in a bindings file replaced.
+fn input_map_from(bytes: &[u8]) -> Result<InputMap<Action>> {
+    let bindings: BindingsFile = toml::from_slice(bytes)?;
+    let mut input_map = default_input_map();
+    for (action, binding) in bindings.actions() {
+        apply_bindings(&mut input_map, action, &binding);
+    }
+    Ok(input_map)
+}
+
+fn apply_bindings(input_map: &mut InputMap<Action>, action: Action, binding: &ActionBindings) {
+    input_map.clear_action(action);
+    for key in &binding.keys {
+        input_map.insert(*key, action);
+    }
+    for button in &binding.buttons {
+        input_map.insert(*button, action);
+    }
+    for axis in &binding.axes {
+        input_map.insert(SingleAxis::from(axis), action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The inputs bound to an action, in the order they were added.
+    fn inputs(input_map: &InputMap<Action>, action: Action) -> Vec<UserInput> {
+        input_map.get(action).iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_override_one_action() {
+        let input_map = input_map_from(
+            br#"
+            [confirm]
+            keys = ["Z"]
+            buttons = ["East"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                UserInput::from(KeyCode::Z),
+                UserInput::from(GamepadButtonType::East)
+            ],
+            inputs(&input_map, Action::Confirm)
+        );
+    }
+
+    #[test]
+    fn test_unlisted_actions_keep_defaults() {
+        let input_map = input_map_from(b"[confirm]\nkeys = [\"Z\"]").unwrap();
+        let defaults = default_input_map();
+        for action in [
+            Action::Up,
+            Action::Down,
+            Action::Skip,
+            Action::Backlog,
+            Action::Cancel,
+        ] {
+            assert_eq!(inputs(&defaults, action), inputs(&input_map, action));
+        }
+
+        let empty = input_map_from(b"").unwrap();
+        assert_eq!(
+            inputs(&defaults, Action::Confirm),
+            inputs(&empty, Action::Confirm)
+        );
+    }
+
+    #[test]
+    fn test_axis_bindings() {
+        let input_map = input_map_from(
+            br#"
+            [up]
+            axes = [{ axis = "RightStickY", threshold = 0.25 }]
+            [down]
+            axes = [{ axis = "RightStickY", threshold = -0.25 }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![UserInput::from(SingleAxis::positive_only(
+                GamepadAxisType::RightStickY,
+                0.25
+            ))],
+            inputs(&input_map, Action::Up)
+        );
+        assert_eq!(
+            vec![UserInput::from(SingleAxis::negative_only(
+                GamepadAxisType::RightStickY,
+                -0.25
+            ))],
+            inputs(&input_map, Action::Down)
+        );
+    }
+
+    #[test]
+    fn test_shipped_bindings() {
+        let input_map = input_map_from(include_bytes!("../../assets/bindings.toml")).unwrap();
+        assert_eq!(default_input_map().len(), input_map.len());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        for src in [
+            // Not an action.
+            "[jump]\nkeys = [\"Space\"]",
+            // Not a kind of binding.
+            "[confirm]\nkey = [\"Space\"]",
+            // Not a key.
+            "[confirm]\nkeys = [\"Spacebar\"]",
+            "[up]\naxes = [{ axis = \"LeftStickZ\", threshold = 0.5 }]",
+        ] {
+            assert!(input_map_from(src.as_bytes()).is_err(), "{src}");
+        }
+    }
+}
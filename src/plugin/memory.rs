@@ -0,0 +1,99 @@
+//! Things the runtime remembers about what the player has done, which outlive
+//! any single menu or passage.
+
+use crate::plugin::Dialogue;
+use crate::talkie_core::{resolve_sequences, PassageGroup, Variations, VisitCounts};
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Identifies a single choice within a dialogue file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChoiceKey {
+    pub dialogue: HandleId,
+    /// Index of the passage group the choice belongs to.
+    pub section: usize,
+    /// Index of the choice within the passage group's list of choices.
+    pub choice: usize,
+}
+
+/// Identifies something within a dialogue file that varies between visits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VariationKey {
+    pub dialogue: HandleId,
+    /// Index of the passage group.
+    pub section: usize,
+    /// `None` for the section's choice of passage, otherwise the passage and
+    /// position of an inline sequence.
+    pub inline: Option<(usize, usize)>,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct DialogueMemory {
+    /// Every choice the player has picked so far.
+    pub taken_choices: HashSet<ChoiceKey>,
+    /// Progress through randomized and cycling passages.
+    pub variations: HashMap<VariationKey, Variations>,
+}
+
+/// How many times each section and choice has been visited, per dialogue
+/// file.
+///
+/// Unlike the rest of what's remembered, this is keyed by path so it can be
+/// kept in a save file and carry on from there.
+#[derive(Resource, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DialogueVisits(pub HashMap<String, VisitCounts>);
+
+impl DialogueVisits {
+    pub fn get(&self, dialogue: &Dialogue) -> Option<&VisitCounts> {
+        self.0.get(&dialogue.path)
+    }
+
+    pub fn visit(&mut self, dialogue: &Dialogue, name: impl Into<String>) {
+        self.0.entry(dialogue.path.clone()).or_default().visit(name);
+    }
+}
+
+impl DialogueMemory {
+    pub fn is_taken(&self, key: &ChoiceKey) -> bool {
+        self.taken_choices.contains(key)
+    }
+
+    /// Work out what to show for the `step`th passage of a visit to a
+    /// section, returning the index of the passage and its text.
+    pub fn resolve_passage(
+        &mut self,
+        dialogue: HandleId,
+        section: usize,
+        group: &PassageGroup,
+        step: usize,
+        rng: &mut impl Rng,
+    ) -> (usize, String) {
+        let mut key = VariationKey {
+            dialogue,
+            section,
+            inline: None,
+        };
+        let passage = if step == 0 && group.visit_len() < group.passages.len() {
+            self.variations
+                .entry(key)
+                .or_default()
+                .next(group.mode, group.passages.len(), rng)
+        } else {
+            step
+        };
+
+        let text = resolve_sequences(&group.passages[passage].text, |seq, mode, count| {
+            key.inline = Some((passage, seq));
+            self.variations
+                .entry(key)
+                .or_default()
+                .next(mode, count, rng)
+        })
+        // Sequences are checked when the dialogue is loaded.
+        .expect("sequence");
+        (passage, text)
+    }
+}
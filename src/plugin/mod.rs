@@ -1,33 +1,136 @@
 use bevy::{
-    asset::{AssetLoader, LoadContext, LoadedAsset},
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
     prelude::*,
     reflect::TypeUuid,
-    utils::BoxedFuture,
+    utils::{BoxedFuture, HashMap},
 };
-use iyes_loopless::prelude::*;
 use leafwing_input_manager::prelude::*;
-use serde::Deserialize;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
+mod backlog;
+mod bark;
 mod billboard;
+mod bubble;
+mod check;
 mod choice;
+mod command;
+#[cfg(feature = "embedded-assets")]
+mod embedded;
 mod goto;
+mod input;
+mod memory;
+mod phase;
 mod playback;
 mod prompt;
+mod text_input;
+mod theme;
+mod variables;
 
-pub struct TalkiePlugin;
+pub use bark::{Bark, Barker};
+pub use billboard::StartConversation;
+pub use bubble::StartBubble;
+pub use check::{SkillChecks, StatProvider};
+pub use command::{CommandFinished, CommandId, DialogueCommand};
+#[cfg(feature = "embedded-assets")]
+pub use embedded::EmbeddedAssetsPlugin;
+pub use memory::DialogueVisits;
+pub use phase::dialogue_active;
+pub use variables::{DialogueVariables, VariableResolver};
+
+/// Pull in the constants generated for a dialogue file by the build script:
+/// `PATH`, to load it with, and one for each section id.
+///
+/// ```ignore
+/// mod shop {
+///     crate::include_dialogue!("dialogue/shop.toml");
+/// }
+///
+/// events.send(StartConversation {
+///     dialogue: ass.load(shop::PATH),
+///     section: Some(shop::COUNTER),
+/// });
+/// ```
+///
+/// The build fails if any dialogue file doesn't pass the checks it would
+/// otherwise only get on load.
+#[macro_export]
+macro_rules! include_dialogue {
+    ($path:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $path, ".rs"));
+    };
+}
+
+/// The id of a section, as checked by the build script, along with the file
+/// it's in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SectionId {
+    /// The file the section is in, as given to `include_dialogue!`.
+    pub path: &'static str,
+    pub id: &'static str,
+}
+
+impl SectionId {
+    /// Whether the section is in the file `dialogue` was loaded from.
+    pub(crate) fn is_in(&self, ass: &AssetServer, dialogue: &Handle<Dialogue>) -> bool {
+        ass.get_handle_path(dialogue)
+            .is_some_and(|path| path.path() == std::path::Path::new(self.path))
+    }
+}
+
+impl std::fmt::Display for SectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.id)
+    }
+}
+
+#[derive(Default)]
+pub struct TalkiePlugin {
+    handlers: command::CommandHandlers,
+}
+
+impl TalkiePlugin {
+    /// Call `handler` whenever the dialogue runs the command `name`.
+    ///
+    /// Every command is also sent as a `DialogueCommand` event, so handlers
+    /// are optional.
+    pub fn with_command(
+        mut self,
+        name: impl Into<String>,
+        handler: impl Fn(&mut World, &DialogueCommand) + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers
+            .0
+            .insert(name.into(), std::sync::Arc::new(handler));
+        self
+    }
+}
 
 impl Plugin for TalkiePlugin {
     fn build(&self, app: &mut App) {
         app.add_asset::<Dialogue>()
             .init_asset_loader::<DialogueLoader>()
+            .init_asset_loader::<CompiledDialogueLoader>()
             .add_plugin(InputManagerPlugin::<Action>::default())
-            .add_loopless_state(GameState::Loading)
+            .init_resource::<memory::DialogueMemory>()
+            .init_resource::<memory::DialogueVisits>()
+            .init_resource::<TalkieRng>()
+            .init_resource::<check::SkillChecks>()
+            .init_resource::<variables::DialogueVariables>()
+            .add_plugin(phase::PhasePlugin)
+            .add_plugin(backlog::BacklogPlugin)
+            .add_plugin(bark::BarkPlugin)
             .add_plugin(billboard::BillboardPlugin)
+            .add_plugin(bubble::BubblePlugin)
             .add_plugin(choice::ChoicePlugin)
+            .add_plugin(command::CommandPlugin {
+                handlers: self.handlers.clone(),
+            })
             .add_plugin(goto::GotoPlugin)
             .add_plugin(prompt::PromptPlugin)
             .add_plugin(playback::PlaybackPlugin)
-            .add_system(debug_current_state);
+            .add_plugin(text_input::TextInputPlugin)
+            .add_plugin(theme::ThemePlugin);
     }
 }
 
@@ -37,14 +140,26 @@ impl Plugin for TalkiePlugin {
 const DEFAULT_GLYPHS_PER_SEC: f32 = 14.0;
 const TALKIE_SPEED_FACTOR: f32 = 10.0;
 
-/// Our Application State
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum GameState {
-    Loading,
-    Choice,
-    Goto,
-    Playback,
-    Prompt,
+/// Where the plugin gets its randomness from, for things like picking barks.
+///
+/// Seeded from the `TALKIE_SEED` env var when it's set, so a playtest can be
+/// replayed. Insert one before adding `TalkiePlugin` to seed it some other way.
+#[derive(Resource)]
+pub struct TalkieRng(pub StdRng);
+
+impl TalkieRng {
+    pub fn seeded(seed: u64) -> Self {
+        TalkieRng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for TalkieRng {
+    fn default() -> Self {
+        match std::env::var("TALKIE_SEED") {
+            Ok(seed) => TalkieRng::seeded(seed.parse().expect("invalid seed.")),
+            Err(_) => TalkieRng(StdRng::from_entropy()),
+        }
+    }
 }
 
 #[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug)]
@@ -52,25 +167,23 @@ pub enum Action {
     Confirm,
     Up,
     Down,
+    /// Reveal the rest of the current passage immediately.
+    Skip,
+    /// Show the history of everything said so far.
+    Backlog,
+    /// Back out of whatever is currently open.
+    Cancel,
 }
 
-/// Despawn all entities with a given component type
-fn despawn_with<T: Component>(mut commands: Commands, q: Query<Entity, With<T>>) {
-    for e in q.iter() {
-        commands.entity(e).despawn_recursive();
-    }
-}
-
-/// Resource used to build a menu of choices.
-#[derive(Debug, Deserialize, TypeUuid)]
+/// A loaded dialogue file.
+#[derive(Debug, TypeUuid)]
 #[uuid = "75348891-801a-447f-9663-0f08e0247859"]
-pub struct Dialogue(crate::talkie_core::Dialogue);
-
-/// We can just access the `CurrentState`, and even use change detection!
-fn debug_current_state(state: Res<CurrentState<GameState>>) {
-    if state.is_changed() {
-        println!("Detected state change to {state:?}!");
-    }
+pub struct Dialogue {
+    pub dialogue: crate::talkie_core::Dialogue,
+    /// The other files it includes, keyed by the name it uses for them.
+    pub modules: HashMap<String, Handle<Dialogue>>,
+    /// Where it was loaded from, which is what visits are saved under.
+    pub path: String,
 }
 
 #[derive(Default)]
@@ -83,8 +196,18 @@ impl AssetLoader for DialogueLoader {
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
         Box::pin(async move {
+            #[cfg(feature = "compiled-dialogue")]
+            if let Some(dialogue) = read_compiled(load_context, bytes).await {
+                finish_loading(dialogue, load_context);
+                return Ok(());
+            }
+
             let dialogue = crate::talkie_core::Dialogue::from_slice(bytes)?;
-            load_context.set_default_asset(LoadedAsset::new(Dialogue(dialogue)));
+            let path = load_context.path().display();
+            for idx in dialogue.graph().unreachable() {
+                eprintln!("Section {idx} of {path} can never be reached.");
+            }
+            finish_loading(dialogue, load_context);
             Ok(())
         })
     }
@@ -93,3 +216,74 @@ impl AssetLoader for DialogueLoader {
         &["toml"]
     }
 }
+
+/// Loads dialogue saved by `talkie compile`.
+#[derive(Default)]
+pub struct CompiledDialogueLoader;
+
+impl AssetLoader for CompiledDialogueLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let dialogue = crate::talkie_core::Dialogue::from_compiled(bytes)?;
+            finish_loading(dialogue, load_context);
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[crate::talkie_core::COMPILED_EXTENSION]
+    }
+}
+
+/// Use the compiled copy of a dialogue file sitting next to it, if there is
+/// one that can be read and it was compiled from the file as it is now.
+///
+/// An edited file is loaded from the TOML until it's compiled again.
+#[cfg(feature = "compiled-dialogue")]
+async fn read_compiled(
+    load_context: &LoadContext<'_>,
+    source: &[u8],
+) -> Option<crate::talkie_core::Dialogue> {
+    let path = load_context
+        .path()
+        .with_extension(crate::talkie_core::COMPILED_EXTENSION);
+    let bytes = load_context.read_asset_bytes(&path).await.ok()?;
+    match crate::talkie_core::Dialogue::from_compiled_source(&bytes, source) {
+        Ok(dialogue) => Some(dialogue),
+        Err(e) => {
+            eprintln!("Ignoring {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Load the other files the dialogue refers to, and hand it over.
+fn finish_loading(dialogue: crate::talkie_core::Dialogue, load_context: &mut LoadContext) {
+    // Other files are found relative to this one.
+    let dir = load_context
+        .path()
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let path = load_context.path().to_string_lossy().into_owned();
+    let mut modules = HashMap::default();
+    let mut dependencies = vec![];
+    for module in dialogue.modules() {
+        let path = dialogue.module_path(module).expect("checked on load");
+        let path = AssetPath::new(dir.join(path), None);
+        modules.insert(module.to_string(), load_context.get_handle(path.clone()));
+        dependencies.push(path);
+    }
+
+    load_context.set_default_asset(
+        LoadedAsset::new(Dialogue {
+            dialogue,
+            modules,
+            path,
+        })
+        .with_dependencies(dependencies),
+    );
+}
@@ -0,0 +1,110 @@
+//! Tracks what each conversation is doing.
+//!
+//! Rather than an app-wide state machine, every conversation entity carries
+//! its own `DialoguePhase`. This keeps the plugin out of the way of whatever
+//! states the host game has for itself.
+
+use crate::plugin::billboard::Modal;
+use bevy::prelude::*;
+
+pub struct PhasePlugin;
+
+impl Plugin for PhasePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PhaseChanged>()
+            // Like the state transitions in iyes_loopless, phase changes are
+            // applied before `Update` so every system sees the same phase for
+            // the whole frame.
+            .add_system_to_stage(CoreStage::PreUpdate, apply_next_phase.label(ApplyNextPhase))
+            .add_system(debug_phase_changes);
+    }
+}
+
+/// Systems that set up or tear down a phase should run in `PreUpdate` after
+/// this, so whatever they spawn is in place before the phase's own systems run.
+#[derive(SystemLabel)]
+pub struct ApplyNextPhase;
+
+/// Where a conversation is up to.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DialoguePhase {
+    /// Waiting on the dialogue asset.
+    Loading,
+    /// Showing a menu of choices.
+    Choice,
+    /// Jumping to another section.
+    Goto,
+    /// Revealing a passage.
+    Playback,
+    /// Waiting for the player to move on from a passage.
+    Prompt,
+    /// Waiting for the player to type something in.
+    Input,
+}
+
+/// Insert on a conversation to move it into another phase at the start of the
+/// next frame.
+#[derive(Component, Debug)]
+pub struct NextPhase(pub DialoguePhase);
+
+/// Sent whenever a conversation moves from one phase to another.
+#[derive(Debug)]
+pub struct PhaseChanged {
+    pub conversation: Entity,
+    pub from: DialoguePhase,
+    pub to: DialoguePhase,
+}
+
+impl PhaseChanged {
+    pub fn entered(&self, phase: DialoguePhase) -> bool {
+        self.to == phase && self.from != phase
+    }
+
+    pub fn exited(&self, phase: DialoguePhase) -> bool {
+        self.from == phase && self.to != phase
+    }
+}
+
+fn apply_next_phase(
+    mut commands: Commands,
+    mut events: EventWriter<PhaseChanged>,
+    mut query: Query<(Entity, &mut DialoguePhase, &NextPhase)>,
+) {
+    for (conversation, mut phase, next) in &mut query {
+        commands.entity(conversation).remove::<NextPhase>();
+        events.send(PhaseChanged {
+            conversation,
+            from: *phase,
+            to: next.0,
+        });
+        *phase = next.0;
+    }
+}
+
+fn debug_phase_changes(mut events: EventReader<PhaseChanged>) {
+    for event in events.iter() {
+        println!(
+            "Detected phase change from {:?} to {:?}!",
+            event.from, event.to
+        );
+    }
+}
+
+/// Run condition: the main conversation is in the given phase.
+pub fn in_phase(
+    phase: DialoguePhase,
+) -> impl FnMut(Query<&DialoguePhase, With<Modal>>) -> bool + Clone + Send + Sync + 'static {
+    move |query: Query<&DialoguePhase, With<Modal>>| query.iter().any(|p| *p == phase)
+}
+
+/// Run condition: a conversation is holding the player's attention.
+///
+/// Host games can use this to pause anything that shouldn't happen while the
+/// player is busy talking, such as player movement:
+///
+/// ```ignore
+/// app.add_system(move_player.run_if_not(dialogue_active));
+/// ```
+pub fn dialogue_active(query: Query<(), With<Modal>>) -> bool {
+    !query.is_empty()
+}
@@ -1,8 +1,18 @@
+use crate::plugin::backlog::{BacklogView, DialogueLog, LogEntry};
 use crate::plugin::billboard::{
-    Billboard, Bookmark, DialogueText, PlayHead, SpeakerNameTab, SpeakerNameText,
+    Billboard, Bookmark, CurrentPassage, CurrentSpeaker, DialogueText, Modal, PlayHead,
+    SpeakerNameTab, SpeakerNameText, SpokenLine,
 };
+use crate::plugin::check::SkillChecks;
 use crate::plugin::choice::Choices;
-use crate::plugin::{Action, Dialogue, GameState, TALKIE_SPEED_FACTOR};
+use crate::plugin::command::{not_waiting, CommandSender, PendingCommands};
+use crate::plugin::goto::Goto;
+use crate::plugin::memory::{DialogueMemory, DialogueVisits};
+use crate::plugin::phase::{in_phase, DialoguePhase, NextPhase};
+use crate::plugin::text_input::TextEntry;
+use crate::plugin::variables::DialogueVariables;
+use crate::plugin::{Action, Dialogue, TalkieRng, TALKIE_SPEED_FACTOR};
+use crate::talkie_core::extract_commands;
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 use leafwing_input_manager::action_state::ActionState;
@@ -13,63 +23,159 @@ impl Plugin for PlaybackPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(
             ConditionSet::new()
-                .run_in_state(GameState::Playback)
+                .run_if(in_phase(DialoguePhase::Playback))
+                .run_if(not_waiting)
+                .run_unless_resource_exists::<BacklogView>()
                 .with_system(input_handler)
                 .with_system(reveal_timer_reset)
                 .with_system(playback_system)
                 .into(),
-        );
+        )
+        .add_system(show_speaker);
     }
 }
 
 fn input_handler(
-    action_state: Query<&ActionState<Action>, With<PlayHead>>,
-    mut playhead: Query<&mut PlayHead>,
+    action_state: Query<&ActionState<Action>, With<Modal>>,
+    mut playhead: Query<&mut PlayHead, With<Modal>>,
 ) {
     let action_state = action_state.single();
     let mut playhead = playhead.single_mut();
     playhead.fast_forward = action_state.pressed(Action::Confirm);
+    if action_state.just_pressed(Action::Skip) {
+        playhead.skip = true;
+    }
 }
 
-fn reveal_timer_reset(mut query: Query<&mut PlayHead, Changed<Bookmark>>) {
+fn reveal_timer_reset(mut query: Query<&mut PlayHead, (Changed<Bookmark>, With<Modal>)>) {
     if let Ok(mut playhead) = query.get_single_mut() {
         println!("Resetting playhead last reveal time");
         playhead.secs_since_last_reveal = None;
     }
 }
 
-#[allow(clippy::type_complexity)]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn playback_system(
     mut commands: Commands,
     time: Res<Time>,
     dialogue: Res<Assets<Dialogue>>,
-    billboard: Query<&Billboard>,
-    mut playback: Query<(&mut PlayHead, &mut Bookmark)>,
-    mut display: ParamSet<(
-        Query<(&mut Visibility, With<SpeakerNameTab>)>,
-        Query<(&mut Text, With<SpeakerNameText>)>,
-        Query<(&mut Text, With<DialogueText>)>,
-    )>,
+    ass: Res<AssetServer>,
+    audio: Res<Audio>,
+    mut log: ResMut<DialogueLog>,
+    mut memory: ResMut<DialogueMemory>,
+    mut rng: ResMut<TalkieRng>,
+    variables: Res<DialogueVariables>,
+    mut visits: ResMut<DialogueVisits>,
+    checks: Res<SkillChecks>,
+    mut sender: CommandSender,
+    billboard: Query<(Entity, &Billboard), With<Modal>>,
+    mut playback: Query<
+        (
+            &mut PlayHead,
+            &mut Bookmark,
+            &mut CurrentSpeaker,
+            &mut CurrentPassage,
+            &mut PendingCommands,
+            &mut Goto,
+            Option<&SpokenLine>,
+        ),
+        With<Modal>,
+    >,
+    mut display: Query<&mut Text, With<DialogueText>>,
 ) {
-    let billboard = billboard.single();
+    let (conversation, billboard) = billboard.single();
     let dialogue = dialogue.get(&billboard.dialogue).expect("dialogue");
-    let (mut playhead, mut bookmark) = playback.single_mut();
-    let group = &dialogue.0.passage_groups[bookmark.passage_group];
-    let entire_text = group.passages[bookmark.passage].as_str();
-
-    if playhead.head < entire_text.len() {
-        {
-            // TODO: refactor so we only do this when the passage group is changing
-            //  Speaker names are by passage group so doing this every tick is needless.
-            let speaker_name = group.speaker.as_deref().unwrap_or("");
-            {
-                let mut t = display.p1();
-                let (mut txt, _) = t.single_mut();
-                txt.sections[0].value = speaker_name.to_string();
+    let (
+        mut playhead,
+        mut bookmark,
+        mut current_speaker,
+        mut current,
+        mut pending,
+        mut goto,
+        spoken,
+    ) = playback.single_mut();
+    let section = bookmark.passage_group;
+    let group = &dialogue.dialogue.passage_groups[bookmark.passage_group];
+    if let (false, Some(line)) = (current.resolved, spoken) {
+        let text = variables.interpolate(&line.text, dialogue, &visits);
+        let (text, inline) = extract_commands(&text).unwrap_or_else(|_| (text, vec![]));
+        *current = CurrentPassage {
+            glyphs: text.chars().count(),
+            text,
+            speaker: line.speaker.clone(),
+            commands: inline,
+            resolved: true,
+            ..default()
+        };
+        start_passage(&current, &mut current_speaker, &mut display.single_mut());
+    } else if !current.resolved {
+        if bookmark.passage == 0 {
+            if let Some(condition) = &group.condition {
+                if !variables.test(condition, dialogue, &visits) {
+                    // Carry on with the following section, as if this one
+                    // weren't there at all.
+                    bookmark.passage_group =
+                        (bookmark.passage_group + 1) % dialogue.dialogue.passage_groups.len();
+                    return;
+                }
+            }
+        }
+        if bookmark.passage == 0 {
+            if let Some(id) = &group.id {
+                visits.visit(dialogue, id.clone());
+            }
+        }
+        let (index, text) = memory.resolve_passage(
+            billboard.dialogue.id(),
+            section,
+            group,
+            bookmark.passage,
+            &mut rng.0,
+        );
+        let text = variables.interpolate(&text, dialogue, &visits);
+        let (text, inline) = extract_commands(&text).unwrap_or_else(|_| (text, vec![]));
+        *current = CurrentPassage {
+            index,
+            glyphs: text.chars().count(),
+            text,
+            speaker: group.speaker_for(index).map(str::to_string),
+            commands: inline,
+            sent: 0,
+            revealed: default(),
+            resolved: true,
+        };
+        start_passage(&current, &mut current_speaker, &mut display.single_mut());
+
+        if bookmark.passage == 0 {
+            for command in &group.on_enter {
+                sender.send(conversation, command, &mut pending);
+            }
+            if !pending.is_empty() {
+                return;
+            }
+        }
+    }
+    let CurrentPassage {
+        index,
+        text: entire_text,
+        glyphs,
+        speaker,
+        commands: inline,
+        sent,
+        revealed,
+        resolved,
+    } = &mut *current;
+
+    let voice = match spoken {
+        Some(_) => None,
+        None => group.voice(*index).map(|path| ass.load(path)),
+    };
+
+    if playhead.head < *glyphs {
+        if playhead.head == 0 && playhead.secs_since_last_reveal.is_none() {
+            if let Some(voice) = &voice {
+                audio.play(voice.clone());
             }
-            let mut v = display.p0();
-            let (mut vis, _) = v.single_mut();
-            vis.is_visible = !speaker_name.trim().is_empty();
         }
 
         let mut since = playhead.secs_since_last_reveal.unwrap_or_default();
@@ -87,20 +193,77 @@ fn playback_system(
 
         playhead.secs_since_last_reveal = Some(remainder);
         playhead.head += reveal_how_many; // Only advance if we can update the display
-        {
-            let mut q = display.p2();
-            let (mut txt, _) = q.single_mut();
-            txt.sections[0].value = entire_text.chars().take(playhead.head).collect();
+        if playhead.skip {
+            playhead.skip = false;
+            playhead.head = *glyphs;
+        }
+
+        // Run the commands that have been reached, stopping short at any that
+        // need to be waited on.
+        while let Some((pos, command)) = inline.get(*sent) {
+            if *pos > playhead.head {
+                break;
+            }
+            sender.send(conversation, command, &mut pending);
+            *sent += 1;
+            if command.wait {
+                playhead.head = *pos;
+                break;
+            }
+        }
+
+        // Left alone on frames where nothing new is revealed, so the text
+        // isn't laid out again for nothing.
+        if revealed.is_behind(playhead.head) {
+            let mut text = display.single_mut();
+            revealed.catch_up(entire_text, playhead.head, &mut text.sections[0].value);
         }
     } else {
-        let last_group = bookmark.passage_group == dialogue.0.passage_groups.len() - 1;
-        let last_passage = bookmark.passage == group.passages.len() - 1;
+        // Anything left over sits at the very end of the passage.
+        if *sent < inline.len() {
+            for (_, command) in &inline[*sent..] {
+                sender.send(conversation, command, &mut pending);
+            }
+            *sent = inline.len();
+            if !pending.is_empty() {
+                return;
+            }
+        }
+
+        log.0.push(LogEntry::Passage {
+            speaker: speaker.clone(),
+            text: entire_text.to_string(),
+            voice,
+        });
+
+        // The bookmark already moved on before the choice was made, so all
+        // that's left is to follow the choice once the line is done.
+        if spoken.is_some() {
+            *resolved = false;
+            playhead.head = 0;
+            playhead.secs_since_last_reveal = None;
+            commands
+                .entity(conversation)
+                .remove::<SpokenLine>()
+                .insert(NextPhase(DialoguePhase::Prompt));
+            return;
+        }
+
+        let last_group = bookmark.passage_group == dialogue.dialogue.passage_groups.len() - 1;
+        let last_passage = bookmark.passage == group.visit_len() - 1;
+        if last_passage {
+            for command in &group.on_exit {
+                sender.send(conversation, command, &mut pending);
+            }
+            goto.0 = group.jump();
+        }
 
         // Go back to the very start if we're at the end of the last
         // passage.
         // If we're at the end of any other passage, reset the head
         // but advance to the next passage.
         // Otherwise, reveal another glyph of the current passage.
+        *resolved = false;
         match (last_passage, last_group) {
             (true, true) => {
                 playhead.head = 0;
@@ -118,17 +281,66 @@ fn playback_system(
             }
         }
 
-        let has_choices = group
-            .choices
-            .as_ref()
-            .map(|v| !v.is_empty())
-            .unwrap_or(false);
+        let mut choices =
+            Choices::for_section(billboard.dialogue.id(), section, group, &memory, |c| {
+                variables.test(c, dialogue, &visits)
+            });
+        for entry in choices.iter_mut().flat_map(|c| c.choices.iter_mut()) {
+            let label = variables.interpolate(&entry.choice.label, dialogue, &visits);
+            entry.choice.label = match &entry.choice.check {
+                Some(check) => {
+                    let odds = (checks.odds(check) * 100.0).round();
+                    format!("[{} {odds}%] {label}", check.stat)
+                }
+                None => label,
+            };
+        }
 
-        if last_passage && has_choices {
-            commands.insert_resource(Choices(group.choices.clone().expect("choices")));
-            commands.insert_resource(NextState(GameState::Choice));
+        if let (true, Some(choices)) = (last_passage, choices) {
+            commands
+                .entity(conversation)
+                .insert((choices, NextPhase(DialoguePhase::Choice)));
+        } else if let (true, Some(input)) = (last_passage, &group.input) {
+            commands.entity(conversation).insert((
+                TextEntry::new(input.clone()),
+                NextPhase(DialoguePhase::Input),
+            ));
         } else {
-            commands.insert_resource(NextState(GameState::Prompt));
+            commands
+                .entity(conversation)
+                .insert(NextPhase(DialoguePhase::Prompt));
         }
     }
 }
+
+/// Clear the box for a passage that's just been picked, and switch speakers
+/// if need be.
+///
+/// Only a new passage can bring a new speaker, so this is the one place the
+/// speaker changes.
+fn start_passage(current: &CurrentPassage, speaker: &mut Mut<CurrentSpeaker>, text: &mut Text) {
+    if speaker.0 != current.speaker {
+        // The theme and name tab pick this up to restyle for the new speaker.
+        speaker.0 = current.speaker.clone();
+    }
+    text.sections[0].value.clear();
+}
+
+/// Put the speaker's name in the name tab, hiding it when nobody in particular
+/// is speaking.
+fn show_speaker(
+    speaker: Query<&CurrentSpeaker, (Changed<CurrentSpeaker>, With<Modal>)>,
+    mut name_tab: Query<&mut Visibility, With<SpeakerNameTab>>,
+    mut name_text: Query<&mut Text, With<SpeakerNameText>>,
+) {
+    let Ok(speaker) = speaker.get_single() else {
+        return;
+    };
+    let name = speaker.0.as_deref().unwrap_or("");
+    if let Ok(mut text) = name_text.get_single_mut() {
+        text.sections[0].value = name.to_string();
+    }
+    if let Ok(mut visibility) = name_tab.get_single_mut() {
+        visibility.is_visible = !name.trim().is_empty();
+    }
+}
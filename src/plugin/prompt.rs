@@ -1,6 +1,10 @@
-use crate::plugin::billboard::PlayHead;
-use crate::plugin::choice::BTN_HEIGHT;
-use crate::plugin::{despawn_with, Action, GameState};
+use crate::plugin::backlog::BacklogView;
+use crate::plugin::billboard::{Billboard, CurrentSpeaker, Modal};
+use crate::plugin::command::not_waiting;
+use crate::plugin::goto::Goto;
+use crate::plugin::phase::{in_phase, ApplyNextPhase, DialoguePhase, NextPhase, PhaseChanged};
+use crate::plugin::theme::TalkieTheme;
+use crate::plugin::Action;
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 use leafwing_input_manager::action_state::ActionState;
@@ -9,11 +13,13 @@ pub struct PromptPlugin;
 
 impl Plugin for PromptPlugin {
     fn build(&self, app: &mut App) {
-        app.add_enter_system(GameState::Prompt, setup_prompt)
-            .add_exit_system(GameState::Prompt, despawn_with::<PromptCursor>)
+        app.add_system_to_stage(CoreStage::PreUpdate, setup_prompt.after(ApplyNextPhase))
+            .add_system_to_stage(CoreStage::PreUpdate, teardown_prompt.after(ApplyNextPhase))
             .add_system_set(
                 ConditionSet::new()
-                    .run_in_state(GameState::Prompt)
+                    .run_if(in_phase(DialoguePhase::Prompt))
+                    .run_if(not_waiting)
+                    .run_unless_resource_exists::<BacklogView>()
                     .with_system(prompt_system)
                     .into(),
             );
@@ -22,31 +28,70 @@ impl Plugin for PromptPlugin {
 
 fn prompt_system(
     mut commands: Commands,
-    action_state: Query<&ActionState<Action>, With<PlayHead>>,
+    action_state: Query<(Entity, &ActionState<Action>, &Goto), With<Modal>>,
 ) {
-    let action_state = action_state.single();
+    let (conversation, action_state, goto) = action_state.single();
     if action_state.just_pressed(Action::Confirm) {
-        commands.insert_resource(NextState(GameState::Playback));
+        // The section might have somewhere else to be once it's over.
+        let next = if goto.0.is_some() {
+            DialoguePhase::Goto
+        } else {
+            DialoguePhase::Playback
+        };
+        commands.entity(conversation).insert(NextPhase(next));
+    } else if action_state.just_pressed(Action::Cancel) {
+        // Walk away from the conversation, handing control back to the game.
+        commands.entity(conversation).despawn_recursive();
     }
 }
 
-fn setup_prompt(mut commands: Commands, _ass: Res<AssetServer>) {
-    let cursor = commands
-        .spawn((
-            NodeBundle {
-                style: Style {
-                    size: Size::new(Val::Px(BTN_HEIGHT), Val::Px(BTN_HEIGHT)),
-                    position_type: PositionType::Absolute,
-                    position: UiRect::new(Val::Auto, Val::Px(14.), Val::Auto, Val::Px(14.)),
-                    ..default()
-                },
-                background_color: Color::rgb(0.9, 0.3, 0.3).into(),
-                ..default()
-            },
-            PromptCursor,
-        ))
-        .id();
-    commands.entity(cursor);
+fn setup_prompt(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    conversations: Query<(&Billboard, &CurrentSpeaker)>,
+) {
+    for event in events.iter() {
+        if !event.entered(DialoguePhase::Prompt) {
+            continue;
+        }
+        let Ok((billboard, speaker)) = conversations.get(event.conversation) else {
+            continue;
+        };
+        let theme = TalkieTheme::current(&themes, billboard, speaker);
+        let cursor = commands
+            .spawn((
+                theme.cursor_bundle(
+                    &ass,
+                    Style {
+                        position_type: PositionType::Absolute,
+                        position: UiRect::new(Val::Auto, Val::Px(14.), Val::Auto, Val::Px(14.)),
+                        ..default()
+                    },
+                ),
+                PromptCursor,
+            ))
+            .id();
+        commands.entity(event.conversation).add_child(cursor);
+    }
+}
+
+fn teardown_prompt(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    cursors: Query<(Entity, &Parent), With<PromptCursor>>,
+) {
+    for event in events.iter() {
+        if !event.exited(DialoguePhase::Prompt) {
+            continue;
+        }
+        for (cursor, parent) in &cursors {
+            if parent.get() == event.conversation {
+                commands.entity(cursor).despawn_recursive();
+            }
+        }
+    }
 }
 
 #[derive(Component)]
@@ -0,0 +1,168 @@
+//! Sections that ask the player to type something in.
+
+use crate::plugin::backlog::{BacklogView, DialogueLog, LogEntry};
+use crate::plugin::billboard::{Billboard, CurrentSpeaker, Modal};
+use crate::plugin::command::not_waiting;
+use crate::plugin::goto::Goto;
+use crate::plugin::phase::{in_phase, ApplyNextPhase, DialoguePhase, NextPhase, PhaseChanged};
+use crate::plugin::theme::TalkieTheme;
+use crate::plugin::variables::DialogueVariables;
+use crate::plugin::Action;
+use crate::talkie_core::TextInput;
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::action_state::ActionState;
+
+pub struct TextInputPlugin;
+
+impl Plugin for TextInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::PreUpdate, setup_field.after(ApplyNextPhase))
+            .add_system_to_stage(CoreStage::PreUpdate, teardown_field.after(ApplyNextPhase))
+            .add_system_set(
+                ConditionSet::new()
+                    .run_if(in_phase(DialoguePhase::Input))
+                    .run_if(not_waiting)
+                    .run_unless_resource_exists::<BacklogView>()
+                    .with_system(typing_system)
+                    .with_system(render_field)
+                    .into(),
+            );
+    }
+}
+
+/// What the player has typed so far, added to a conversation before it moves
+/// into the input phase.
+#[derive(Component, Debug)]
+pub struct TextEntry {
+    input: TextInput,
+    value: String,
+    /// Why the last attempt to confirm was turned down.
+    error: Option<String>,
+}
+
+impl TextEntry {
+    pub fn new(input: TextInput) -> Self {
+        TextEntry {
+            input,
+            value: String::new(),
+            error: None,
+        }
+    }
+}
+
+#[derive(Component)]
+struct TextField;
+
+fn typing_system(
+    mut commands: Commands,
+    mut chars: EventReader<ReceivedCharacter>,
+    keys: Res<Input<KeyCode>>,
+    mut log: ResMut<DialogueLog>,
+    mut variables: ResMut<DialogueVariables>,
+    mut query: Query<(Entity, &ActionState<Action>, &mut TextEntry, &mut Goto), With<Modal>>,
+) {
+    let (conversation, action_state, mut entry, mut goto) = query.single_mut();
+
+    // Space confirms by default, but while typing it's needed for the text.
+    let mut typed_space = false;
+    for event in chars.iter() {
+        if entry.input.accepts(&entry.value, event.char) {
+            entry.value.push(event.char);
+            entry.error = None;
+            typed_space |= event.char == ' ';
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) || action_state.just_pressed(Action::Cancel) {
+        entry.value.pop();
+        entry.error = None;
+    }
+
+    if typed_space || !action_state.just_pressed(Action::Confirm) {
+        return;
+    }
+    if let Err(e) = entry.input.validate(&entry.value) {
+        entry.error = Some(e.to_string());
+        return;
+    }
+
+    let value = entry.value.trim().to_string();
+    log.0.push(LogEntry::Choice {
+        label: value.clone(),
+    });
+    if let Some(jump) = entry.input.jump(&value) {
+        goto.0 = Some(jump);
+    }
+    variables.set(entry.input.variable.clone(), value);
+
+    // Like the prompt, the section might have somewhere else to be.
+    let next = if goto.0.is_some() {
+        DialoguePhase::Goto
+    } else {
+        DialoguePhase::Playback
+    };
+    commands.entity(conversation).insert(NextPhase(next));
+}
+
+fn render_field(
+    entries: Query<&TextEntry, (With<Modal>, Changed<TextEntry>)>,
+    mut fields: Query<&mut Text, With<TextField>>,
+) {
+    let (Ok(entry), Ok(mut text)) = (entries.get_single(), fields.get_single_mut()) else {
+        return;
+    };
+    text.sections[0].value = format!("> {}_", entry.value);
+    text.sections[1].value = match &entry.error {
+        Some(error) => format!("\n{error}"),
+        None => String::new(),
+    };
+}
+
+fn setup_field(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    conversations: Query<(&Billboard, &CurrentSpeaker), With<TextEntry>>,
+) {
+    for event in events.iter() {
+        if !event.entered(DialoguePhase::Input) {
+            continue;
+        }
+        let Ok((billboard, speaker)) = conversations.get(event.conversation) else {
+            continue;
+        };
+        let theme = TalkieTheme::current(&themes, billboard, speaker);
+        let style = theme.fonts.choices.text_style(&ass);
+        let mut error_style = style.clone();
+        error_style.color = theme.choices.taken_color.into();
+        let inset = Val::Px(theme.billboard.margin + theme.billboard.padding);
+
+        let mut field = TextBundle::from_sections([
+            TextSection::new("> _", style),
+            TextSection::new("", error_style),
+        ]);
+        field.style.position_type = PositionType::Absolute;
+        field.style.position = UiRect::new(inset, Val::Auto, Val::Auto, inset);
+        let field = commands.spawn((field, TextField)).id();
+        commands.entity(event.conversation).add_child(field);
+    }
+}
+
+fn teardown_field(
+    mut commands: Commands,
+    mut events: EventReader<PhaseChanged>,
+    fields: Query<(Entity, &Parent), With<TextField>>,
+) {
+    for event in events.iter() {
+        if !event.exited(DialoguePhase::Input) {
+            continue;
+        }
+        commands.entity(event.conversation).remove::<TextEntry>();
+        for (field, parent) in &fields {
+            if parent.get() == event.conversation {
+                commands.entity(field).despawn_recursive();
+            }
+        }
+    }
+}
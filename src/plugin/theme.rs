@@ -0,0 +1,646 @@
+//! Look and feel for the dialogue UI.
+//!
+//! Everything about how the billboard is drawn (geometry, colors, fonts, the
+//! cursor) comes from a `TalkieTheme`, loaded from a `*.theme.toml` asset. The
+//! asset is watched, so edits to the file are picked up while the game runs.
+//!
+//! Themes can carry overrides per speaker, which are applied whenever the
+//! speaker changes.
+
+use crate::plugin::billboard::{
+    Billboard, CurrentSpeaker, DialogueText, Modal, Portrait, Root, SpeakerNameTab, SpeakerNameText,
+};
+use anyhow::{anyhow, Result};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{Extent3d, TextureDimension},
+    render::texture::TextureFormatPixelInfo,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<TalkieTheme>()
+            .init_asset_loader::<ThemeLoader>()
+            .init_resource::<SlicedImages>()
+            .add_system(apply_theme)
+            .add_system(update_backdrop);
+    }
+}
+
+/// The theme used when the billboard is set up.
+pub const DEFAULT_THEME_PATH: &str = "default.theme.toml";
+
+#[derive(Debug, Clone, Default, Deserialize, TypeUuid)]
+#[uuid = "6b4a3e0e-3f0c-4c63-9d38-2a1b7f0e6f41"]
+#[serde(default)]
+pub struct TalkieTheme {
+    pub billboard: BoxTheme,
+    pub name_tab: NameTabTheme,
+    pub fonts: Fonts,
+    pub choices: ChoiceTheme,
+    pub cursor: CursorTheme,
+    pub portrait: PortraitTheme,
+    /// Overrides keyed by speaker name.
+    pub speakers: HashMap<String, SpeakerTheme>,
+}
+
+/// Geometry and background for the main text box.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BoxTheme {
+    pub height: f32,
+    pub padding: f32,
+    pub margin: f32,
+    pub background: ThemeColor,
+    /// An image drawn over the background color, stretched to fit the box
+    /// without distorting its corners.
+    pub nine_slice: Option<NineSlice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NameTabTheme {
+    pub padding: f32,
+    pub border: f32,
+    /// Falls back to the billboard background when not set.
+    pub background: Option<ThemeColor>,
+}
+
+/// An image to be cut into a 3x3 grid.
+///
+/// The corners are drawn as-is, the edges are stretched along one axis, and the
+/// middle is stretched to fill whatever is left.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NineSlice {
+    pub image: String,
+    /// Size of the corners, in pixels.
+    pub border: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Fonts {
+    /// The passage text.
+    pub body: FontTheme,
+    /// The speaker name tab.
+    pub name: FontTheme,
+    /// Items in the choice menu.
+    pub choices: FontTheme,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FontTheme {
+    pub font: String,
+    pub size: f32,
+    pub color: ThemeColor,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChoiceTheme {
+    /// Used in place of the font color for choices that were picked before.
+    pub taken_color: ThemeColor,
+    pub countdown_color: ThemeColor,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CursorTheme {
+    pub size: f32,
+    /// Used to tint the image, or as a solid square when there's no image.
+    pub color: ThemeColor,
+    pub image: Option<String>,
+}
+
+/// A picture of whoever is speaking, shown above the corner of the box
+/// opposite the name tab.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PortraitTheme {
+    pub size: f32,
+    /// Usually set per speaker. Nothing is shown without one.
+    pub image: Option<String>,
+}
+
+/// Settings that can differ from one speaker to the next.
+///
+/// Anything left out falls back to the main theme.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SpeakerTheme {
+    pub background: Option<ThemeColor>,
+    pub nine_slice: Option<NineSlice>,
+    pub font: Option<String>,
+    pub text_color: Option<ThemeColor>,
+    pub name_color: Option<ThemeColor>,
+    pub portrait: Option<String>,
+}
+
+/// A color written as a hex string, such as `"#666699"` or `"#000000d9"`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Color::hex(value.trim_start_matches('#'))
+            .map(ThemeColor)
+            .map_err(|err| format!("invalid color `{value}`: {err}"))
+    }
+}
+
+impl From<ThemeColor> for Color {
+    fn from(color: ThemeColor) -> Self {
+        color.0
+    }
+}
+
+impl From<ThemeColor> for BackgroundColor {
+    fn from(color: ThemeColor) -> Self {
+        BackgroundColor(color.0)
+    }
+}
+
+impl Default for BoxTheme {
+    fn default() -> Self {
+        BoxTheme {
+            height: 300.0,
+            padding: 20.0,
+            margin: 20.0,
+            background: ThemeColor(Color::rgb(0.4, 0.4, 0.6)),
+            nine_slice: None,
+        }
+    }
+}
+
+impl Default for NameTabTheme {
+    fn default() -> Self {
+        NameTabTheme {
+            padding: 14.0,
+            border: 4.0,
+            background: None,
+        }
+    }
+}
+
+impl Default for PortraitTheme {
+    fn default() -> Self {
+        PortraitTheme {
+            size: 128.0,
+            image: None,
+        }
+    }
+}
+
+impl Default for FontTheme {
+    fn default() -> Self {
+        FontTheme {
+            font: "Sansation-Regular.ttf".into(),
+            size: 20.0,
+            color: ThemeColor(Color::WHITE),
+        }
+    }
+}
+
+impl Default for ChoiceTheme {
+    fn default() -> Self {
+        ChoiceTheme {
+            taken_color: ThemeColor(Color::rgb(0.6, 0.6, 0.6)),
+            countdown_color: ThemeColor(Color::rgb(0.9, 0.3, 0.3)),
+        }
+    }
+}
+
+impl Default for CursorTheme {
+    fn default() -> Self {
+        CursorTheme {
+            size: 28.0,
+            color: ThemeColor(Color::rgb(0.9, 0.3, 0.3)),
+            image: None,
+        }
+    }
+}
+
+impl TalkieTheme {
+    /// Look up the theme for the given billboard, falling back to the defaults
+    /// while the asset is still loading.
+    pub fn current(
+        themes: &Assets<TalkieTheme>,
+        billboard: &Billboard,
+        speaker: &CurrentSpeaker,
+    ) -> TalkieTheme {
+        themes
+            .get(&billboard.theme)
+            .cloned()
+            .unwrap_or_default()
+            .for_speaker(speaker.0.as_deref())
+    }
+
+    /// Produce a copy of the theme with any overrides for the speaker applied.
+    pub fn for_speaker(mut self, speaker: Option<&str>) -> TalkieTheme {
+        let Some(overrides) = speaker.and_then(|name| self.speakers.get(name)).cloned() else {
+            return self;
+        };
+        if let Some(background) = overrides.background {
+            self.billboard.background = background;
+        }
+        if let Some(nine_slice) = overrides.nine_slice {
+            self.billboard.nine_slice = Some(nine_slice);
+        }
+        if let Some(font) = overrides.font {
+            self.fonts.body.font = font;
+        }
+        if let Some(color) = overrides.text_color {
+            self.fonts.body.color = color;
+        }
+        if let Some(color) = overrides.name_color {
+            self.fonts.name.color = color;
+        }
+        if let Some(portrait) = overrides.portrait {
+            self.portrait.image = Some(portrait);
+        }
+        self
+    }
+
+    pub fn name_tab_background(&self) -> ThemeColor {
+        self.name_tab
+            .background
+            .unwrap_or(self.billboard.background)
+    }
+
+    pub fn root_style(&self) -> Style {
+        Style {
+            position: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Auto, Val::Px(0.0)),
+            padding: UiRect::all(Val::Px(self.billboard.padding)),
+            margin: UiRect::all(Val::Px(self.billboard.margin)),
+            size: Size::new(Val::Auto, Val::Px(self.billboard.height)),
+            ..default()
+        }
+    }
+
+    pub fn name_tab_style(&self) -> Style {
+        Style {
+            position_type: PositionType::Absolute,
+            position: UiRect::bottom(Val::Px(self.billboard.height)),
+            padding: UiRect::all(Val::Px(self.name_tab.padding)),
+            border: UiRect::all(Val::Px(self.name_tab.border)),
+            ..default()
+        }
+    }
+
+    pub fn portrait_style(&self) -> Style {
+        Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(0.0),
+                bottom: Val::Px(self.billboard.height),
+                ..default()
+            },
+            size: Size::new(Val::Px(self.portrait.size), Val::Px(self.portrait.size)),
+            ..default()
+        }
+    }
+
+    /// A node showing the cursor, placed with the given style.
+    pub fn cursor_bundle(&self, ass: &AssetServer, mut style: Style) -> ImageBundle {
+        style.size = Size::new(Val::Px(self.cursor.size), Val::Px(self.cursor.size));
+        ImageBundle {
+            style,
+            image: self
+                .cursor
+                .image
+                .as_ref()
+                .map(|path| UiImage(ass.load(path.as_str())))
+                .unwrap_or_default(),
+            background_color: self.cursor.color.into(),
+            ..default()
+        }
+    }
+}
+
+impl FontTheme {
+    pub fn text_style(&self, ass: &AssetServer) -> TextStyle {
+        TextStyle {
+            font: ass.load(self.font.as_str()),
+            font_size: self.size,
+            color: self.color.into(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ThemeLoader;
+
+impl AssetLoader for ThemeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let theme: TalkieTheme = toml::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(theme));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["theme.toml"]
+    }
+}
+
+/// Tracks the nine-slice image drawn behind the billboard text.
+///
+/// The pieces are (re)built by `update_backdrop` once the image has loaded.
+#[derive(Component, Debug, Default)]
+pub struct Backdrop {
+    nine_slice: Option<(Handle<Image>, u32)>,
+    built: bool,
+}
+
+#[derive(Component)]
+struct BackdropPiece;
+
+/// Cache of images that have already been cut up for nine-slicing.
+#[derive(Resource, Default)]
+struct SlicedImages(HashMap<(Handle<Image>, u32), [Handle<Image>; 9]>);
+
+/// Restyle the billboard whenever the theme asset changes or a new speaker
+/// takes over.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn apply_theme(
+    ass: Res<AssetServer>,
+    themes: Res<Assets<TalkieTheme>>,
+    mut events: EventReader<AssetEvent<TalkieTheme>>,
+    billboard: Query<(&Billboard, &CurrentSpeaker, ChangeTrackers<CurrentSpeaker>), With<Modal>>,
+    mut root: Query<(&mut Style, &mut BackgroundColor, &mut Backdrop), With<Root>>,
+    mut name_tab: Query<(&mut Style, &mut BackgroundColor), (With<SpeakerNameTab>, Without<Root>)>,
+    mut name_text: Query<&mut Text, With<SpeakerNameText>>,
+    mut body_text: Query<&mut Text, (With<DialogueText>, Without<SpeakerNameText>)>,
+    mut portrait: Query<
+        (&mut Style, &mut UiImage, &mut Visibility),
+        (With<Portrait>, Without<Root>, Without<SpeakerNameTab>),
+    >,
+) {
+    let Ok((billboard, speaker, speaker_tracker)) = billboard.get_single() else {
+        return;
+    };
+    let theme_changed = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == billboard.theme
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    if !theme_changed && !speaker_tracker.is_changed() {
+        return;
+    }
+
+    let theme = TalkieTheme::current(&themes, billboard, speaker);
+
+    if let Ok((mut style, mut color, mut backdrop)) = root.get_single_mut() {
+        *style = theme.root_style();
+        *color = theme.billboard.background.into();
+        let nine_slice = theme
+            .billboard
+            .nine_slice
+            .as_ref()
+            .map(|slice| (ass.load(slice.image.as_str()), slice.border));
+        if backdrop.nine_slice != nine_slice {
+            *backdrop = Backdrop {
+                nine_slice,
+                built: false,
+            };
+        }
+    }
+    if let Ok((mut style, mut color)) = name_tab.get_single_mut() {
+        *style = theme.name_tab_style();
+        *color = theme.name_tab_background().into();
+    }
+    for mut text in &mut name_text {
+        text.sections[0].style = theme.fonts.name.text_style(&ass);
+    }
+    for mut text in &mut body_text {
+        text.sections[0].style = theme.fonts.body.text_style(&ass);
+    }
+    if let Ok((mut style, mut image, mut visibility)) = portrait.get_single_mut() {
+        *style = theme.portrait_style();
+        visibility.is_visible = theme.portrait.image.is_some();
+        if let Some(path) = &theme.portrait.image {
+            *image = UiImage(ass.load(path.as_str()));
+        }
+    }
+}
+
+/// Swap out the pieces of the nine-slice backdrop when it changes.
+fn update_backdrop(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut sliced: ResMut<SlicedImages>,
+    mut root: Query<(Entity, &mut Backdrop)>,
+    pieces: Query<Entity, With<BackdropPiece>>,
+) {
+    let Ok((root, mut backdrop)) = root.get_single_mut() else {
+        return;
+    };
+    if backdrop.built {
+        return;
+    }
+
+    for piece in &pieces {
+        commands.entity(piece).despawn_recursive();
+    }
+
+    let Some((source, border)) = backdrop.nine_slice.clone() else {
+        backdrop.built = true;
+        return;
+    };
+
+    if !sliced.0.contains_key(&(source.clone(), border)) {
+        // Keep checking back until the image has loaded.
+        let Some(image) = images.get(&source) else {
+            return;
+        };
+        match slice_image(image, border) {
+            Ok(slices) => {
+                let handles = slices.map(|slice| images.add(slice));
+                sliced.0.insert((source.clone(), border), handles);
+            }
+            Err(err) => {
+                eprintln!("Unable to nine-slice backdrop: {err}");
+                backdrop.built = true;
+                return;
+            }
+        }
+    }
+
+    let handles = &sliced.0[&(source, border)];
+    let border = Val::Px(border as f32);
+    // Positions for the left/top, middle, and right/bottom of each axis.
+    let spans = [
+        (Val::Px(0.0), Val::Auto, border),
+        (border, border, Val::Auto),
+        (Val::Auto, Val::Px(0.0), border),
+    ];
+
+    commands.entity(root).with_children(|parent| {
+        for (row, (top, bottom, height)) in spans.iter().enumerate() {
+            for (col, (left, right, width)) in spans.iter().enumerate() {
+                parent.spawn((
+                    ImageBundle {
+                        image: UiImage(handles[row * 3 + col].clone()),
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect::new(*left, *right, *top, *bottom),
+                            size: Size::new(*width, *height),
+                            ..default()
+                        },
+                        // Keep the pieces behind the text.
+                        z_index: ZIndex::Local(-1),
+                        ..default()
+                    },
+                    BackdropPiece,
+                ));
+            }
+        }
+    });
+    backdrop.built = true;
+}
+
+/// Cut an image into a 3x3 grid, row by row from the top left.
+///
+/// Block-compressed images (as KTX2 and DDS files often are) can't be cut up
+/// pixel by pixel, so they're refused.
+fn slice_image(image: &Image, border: u32) -> Result<[Image; 9]> {
+    let format = image.texture_descriptor.format;
+    if format.describe().block_dimensions != (1, 1) {
+        return Err(anyhow!(
+            "{format:?} images are compressed, so can't be sliced"
+        ));
+    }
+    let pixel_size = format.pixel_size();
+    let Extent3d { width, height, .. } = image.texture_descriptor.size;
+    if border * 2 >= width || border * 2 >= height {
+        return Err(anyhow!(
+            "border of {border}px is too big for a {width}x{height} image"
+        ));
+    }
+
+    let spans = |len: u32| [(0, border), (border, len - border), (len - border, len)];
+    let mut slices = Vec::with_capacity(9);
+    for (y0, y1) in spans(height) {
+        for (x0, x1) in spans(width) {
+            let mut data = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize * pixel_size);
+            for y in y0..y1 {
+                let start = (y * width + x0) as usize * pixel_size;
+                let end = (y * width + x1) as usize * pixel_size;
+                data.extend_from_slice(&image.data[start..end]);
+            }
+            slices.push(Image::new(
+                Extent3d {
+                    width: x1 - x0,
+                    height: y1 - y0,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                format,
+            ));
+        }
+    }
+    Ok(slices.try_into().expect("nine slices"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::TextureFormat;
+
+    #[test]
+    fn test_theme_color() {
+        let color = |s: &str| ThemeColor::try_from(s.to_string());
+        assert_eq!(
+            Ok(ThemeColor(Color::rgb_u8(0x66, 0x66, 0x99))),
+            color("#666699")
+        );
+        assert_eq!(
+            Ok(ThemeColor(Color::rgb_u8(0x66, 0x66, 0x99))),
+            color("666699")
+        );
+        assert_eq!(
+            Ok(ThemeColor(Color::rgba_u8(0, 0, 0, 0xd9))),
+            color("#000000d9")
+        );
+        for bad in ["", "#", "#12345", "#gggggg", "blue"] {
+            let err = color(bad).unwrap_err();
+            assert!(err.contains(&format!("`{bad}`")), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_slice_image() {
+        // A 4x4 image where each pixel's red channel is its index.
+        let data = (0..16u8).flat_map(|idx| [idx, 0, 0, 255]).collect();
+        let image = Image::new(
+            Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let slices = slice_image(&image, 1).unwrap();
+        let reds = |slice: &Image| -> Vec<u8> { slice.data.chunks(4).map(|px| px[0]).collect() };
+        let sizes: Vec<_> = slices
+            .iter()
+            .map(|slice| {
+                let size = slice.texture_descriptor.size;
+                (size.width, size.height)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 1),
+                (2, 1),
+                (1, 1),
+                (1, 2),
+                (2, 2),
+                (1, 2),
+                (1, 1),
+                (2, 1),
+                (1, 1)
+            ],
+            sizes
+        );
+        assert_eq!(vec![0], reds(&slices[0]));
+        assert_eq!(vec![1, 2], reds(&slices[1]));
+        assert_eq!(vec![4, 8], reds(&slices[3]));
+        assert_eq!(vec![5, 6, 9, 10], reds(&slices[4]));
+        assert_eq!(vec![15], reds(&slices[8]));
+
+        assert!(slice_image(&image, 2).is_err());
+    }
+
+    #[test]
+    fn test_slice_compressed_image() {
+        let mut image = Image::default();
+        image.texture_descriptor.format = TextureFormat::Bc1RgbaUnorm;
+        image.texture_descriptor.size = Extent3d {
+            width: 8,
+            height: 8,
+            depth_or_array_layers: 1,
+        };
+        let err = slice_image(&image, 2).unwrap_err();
+        assert!(err.to_string().contains("compressed"), "{err}");
+    }
+}
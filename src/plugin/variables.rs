@@ -0,0 +1,122 @@
+//! Values that get filled in to placeholders like `{player_name}`, and
+//! checked by conditions.
+
+use crate::plugin::memory::DialogueVisits;
+use crate::plugin::Dialogue;
+use crate::talkie_core::{interpolate, Condition, Env, Value, VisitCounts};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+/// Supplies values the dialogue lists as `externs`, so the game doesn't have
+/// to copy them into `DialogueVariables` ahead of time.
+///
+/// Closures taking the name of the value work as resolvers too.
+pub trait VariableResolver: Send + Sync + 'static {
+    fn resolve(&self, name: &str) -> Option<Value>;
+}
+
+impl<F> VariableResolver for F
+where
+    F: Fn(&str) -> Option<Value> + Send + Sync + 'static,
+{
+    fn resolve(&self, name: &str) -> Option<Value> {
+        self(name)
+    }
+}
+
+/// A function the game provides for conditions to call, like
+/// `has_item("keycard")`.
+pub type DialogueFunction = Box<dyn Fn(&[Value]) -> Option<Value> + Send + Sync>;
+
+/// The dialogue variable store.
+///
+/// Variables a dialogue declares start out with the values it gives them and
+/// can be changed with `set`. Anything else is asked of the resolvers.
+#[derive(Resource, Default)]
+pub struct DialogueVariables {
+    values: HashMap<String, Value>,
+    resolvers: Vec<Box<dyn VariableResolver>>,
+    functions: HashMap<String, DialogueFunction>,
+}
+
+impl DialogueVariables {
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.values.insert(name.into(), value.into());
+    }
+
+    pub fn add_resolver(&mut self, resolver: impl VariableResolver) {
+        self.resolvers.push(Box::new(resolver));
+    }
+
+    /// Provide a function the dialogue declares under `[functions]`.
+    pub fn add_function(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[Value]) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.functions.insert(name.into(), Box::new(function));
+    }
+
+    /// The current value of a variable, as seen from the given dialogue.
+    pub fn get(&self, name: &str, dialogue: &Dialogue) -> Option<Value> {
+        if let Some(value) = self.values.get(name) {
+            return Some(value.clone());
+        }
+        if let Some(value) = dialogue.dialogue.variables.get(name) {
+            return Some(value.clone());
+        }
+        self.resolvers
+            .iter()
+            .find_map(|resolver| resolver.resolve(name))
+    }
+
+    /// Fill in the placeholders in some text from the given dialogue.
+    pub fn interpolate(&self, text: &str, dialogue: &Dialogue, visits: &DialogueVisits) -> String {
+        let scoped = self.scoped(dialogue, visits);
+        interpolate(text, |name| scoped.var(name))
+    }
+
+    /// Whether a condition from the given dialogue holds right now.
+    pub fn test(
+        &self,
+        condition: &Condition,
+        dialogue: &Dialogue,
+        visits: &DialogueVisits,
+    ) -> bool {
+        condition.test(&self.scoped(dialogue, visits))
+    }
+
+    fn scoped<'a>(&'a self, dialogue: &'a Dialogue, visits: &'a DialogueVisits) -> Scoped<'a> {
+        Scoped {
+            variables: self,
+            dialogue,
+            visits: visits.get(dialogue),
+        }
+    }
+}
+
+/// The variables as seen from a particular dialogue.
+struct Scoped<'a> {
+    variables: &'a DialogueVariables,
+    dialogue: &'a Dialogue,
+    visits: Option<&'a VisitCounts>,
+}
+
+impl Env for Scoped<'_> {
+    fn var(&self, name: &str) -> Option<Value> {
+        let empty = VisitCounts::default();
+        if let Some(value) = self.visits.unwrap_or(&empty).lookup(name) {
+            return Some(value);
+        }
+        self.variables.get(name, self.dialogue)
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value> {
+        let visits = |visited| self.visits.map(|v| v.get(visited)).unwrap_or_default();
+        match (name, args) {
+            ("visits", [Value::Text(visited)]) => Some(f64::from(visits(visited)).into()),
+            ("seen", [Value::Text(visited)]) => Some((visits(visited) > 0).into()),
+            _ => self.variables.functions.get(name)?(args),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Commands let the dialogue ask the game to do something, such as handing
+//! over an item or starting a cutscene.
+
+use anyhow::{bail, Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Something for the game to do, written as `<<name arg1 arg2>>` in a passage
+/// or as `"name arg1 arg2"` in a section's `on_enter` or `on_exit` list.
+///
+/// Arguments are split on whitespace, unless they're wrapped in double quotes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<String>,
+    /// Written with `await` in front, as in `<<await cutscene intro>>`, to hold
+    /// playback until the game says the command is done.
+    pub wait: bool,
+}
+
+impl Command {
+    pub fn parse(src: &str) -> Result<Command> {
+        let mut words = vec![];
+        let mut rest = src.trim();
+        while !rest.is_empty() {
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let Some(end) = quoted.find('"') else {
+                    bail!("unclosed quote in `{src}`");
+                };
+                words.push(quoted[..end].to_string());
+                rest = quoted[end + 1..].trim_start();
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                words.push(rest[..end].to_string());
+                rest = rest[end..].trim_start();
+            }
+        }
+
+        let wait = words.first().map(String::as_str) == Some("await");
+        let mut words = words.into_iter().skip(usize::from(wait));
+        let Some(name) = words.next() else {
+            bail!("command `{src}` has no name");
+        };
+        Ok(Command {
+            name,
+            args: words.collect(),
+            wait,
+        })
+    }
+}
+
+impl TryFrom<String> for Command {
+    type Error = Error;
+
+    fn try_from(src: String) -> Result<Self> {
+        Command::parse(&src)
+    }
+}
+
+/// Written back out the way `parse` reads it.
+impl From<Command> for String {
+    fn from(command: Command) -> Self {
+        let wait = command.wait.then_some("await".to_string());
+        let args = command.args.into_iter().map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("\"{arg}\"")
+            } else {
+                arg
+            }
+        });
+        wait.into_iter()
+            .chain([command.name])
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Pull the `<<command>>` markers out of some text.
+///
+/// Returns the text without the markers, and each command along with the
+/// number of glyphs that come before it.
+pub fn extract_commands(text: &str) -> Result<(String, Vec<(usize, Command)>)> {
+    let mut out = String::with_capacity(text.len());
+    let mut commands = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("<<") {
+        let Some(len) = rest[start..].find(">>") else {
+            bail!("unclosed `<<` in `{text}`");
+        };
+        out.push_str(&rest[..start]);
+        let command = Command::parse(&rest[start + 2..start + len])?;
+        commands.push((out.chars().count(), command));
+        rest = &rest[start + len + 2..];
+        // Don't leave a double space where the marker was.
+        if out.ends_with(char::is_whitespace) || out.is_empty() {
+            rest = rest.strip_prefix(' ').unwrap_or(rest);
+        }
+    }
+    out.push_str(rest);
+    Ok((out, commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let command = Command::parse(r#"give_item "rusty sword" 1"#).unwrap();
+        assert_eq!("give_item", command.name);
+        assert_eq!(vec!["rusty sword", "1"], command.args);
+        assert!(!command.wait);
+    }
+
+    #[test]
+    fn test_parse_await() {
+        let command = Command::parse("await cutscene intro").unwrap();
+        assert_eq!("cutscene", command.name);
+        assert_eq!(vec!["intro"], command.args);
+        assert!(command.wait);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert!(Command::parse("  ").is_err());
+        assert!(Command::parse("await").is_err());
+    }
+
+    #[test]
+    fn test_write_back() {
+        for src in [r#"await give_item "rusty sword" "" 1"#, "wave"] {
+            let command = Command::parse(src).unwrap();
+            assert_eq!(src, String::from(command));
+        }
+    }
+
+    #[test]
+    fn test_extract_commands() {
+        let (text, commands) =
+            extract_commands("Take this. <<give_item sword>> Good luck.<<wave>>").unwrap();
+        assert_eq!("Take this. Good luck.", text);
+        assert_eq!(
+            vec![(11, "give_item"), (21, "wave")],
+            commands
+                .iter()
+                .map(|(pos, c)| (*pos, c.name.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_unclosed_marker() {
+        assert!(extract_commands("Oops <<give_item").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Dialogue that's been checked ahead of time and saved in a binary form, so
+//! shipping builds can skip parsing TOML and reflowing text on load.
+//!
+//! A compiled file is `MAGIC`, then the format version as a little-endian
+//! `u16`, then a fingerprint of the TOML it was compiled from as a
+//! little-endian `u64`, then the dialogue itself encoded as CBOR.
+
+use super::{Dialogue, SectionGraph};
+use anyhow::{bail, Result};
+
+/// What every compiled dialogue file starts with.
+pub const MAGIC: &[u8; 4] = b"TLKD";
+
+/// Bumped whenever the dialogue types change in a way that stops older
+/// compiled files from loading properly.
+pub const VERSION: u16 = 2;
+
+/// The file extension for compiled dialogue.
+pub const EXTENSION: &str = "talkie";
+
+/// FNV-1a, which is plenty to tell whether a file has changed and gives the
+/// same answer no matter what built it.
+fn fingerprint(source: &[u8]) -> u64 {
+    source.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Split a compiled file into the fingerprint of its source and the encoded
+/// dialogue.
+fn split(bytes: &[u8]) -> Result<(u64, &[u8])> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        bail!("not compiled dialogue");
+    };
+    let (Some(version), Some(source), Some(body)) =
+        (rest.get(..2), rest.get(2..10), rest.get(10..))
+    else {
+        bail!("compiled dialogue is cut short");
+    };
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version != VERSION {
+        bail!("compiled dialogue is version {version}, but only version {VERSION} can be read");
+    }
+    Ok((u64::from_le_bytes(source.try_into()?), body))
+}
+
+impl Dialogue {
+    /// Save the dialogue in the compiled form. It should have come from
+    /// `from_slice(source)`, so it's known to be good.
+    // Only the `talkie` tool and the build script compile dialogue, not the
+    // game.
+    #[allow(dead_code)]
+    pub fn to_compiled(&self, source: &[u8]) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(fingerprint(source).to_le_bytes());
+        ciborium::ser::into_writer(self, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Load dialogue saved by `to_compiled`.
+    ///
+    /// None of the checks from `from_slice` are made again, since they were
+    /// made when it was compiled.
+    pub fn from_compiled(bytes: &[u8]) -> Result<Dialogue> {
+        let (_, body) = split(bytes)?;
+        Self::decode(body)
+    }
+
+    /// Load dialogue saved by `to_compiled`, but only if it was compiled from
+    /// `source` as it is now, and so isn't out of date.
+    // Only used with the `compiled-dialogue` feature.
+    #[allow(dead_code)]
+    pub fn from_compiled_source(bytes: &[u8], source: &[u8]) -> Result<Dialogue> {
+        let (compiled_from, body) = split(bytes)?;
+        if compiled_from != fingerprint(source) {
+            bail!("it was compiled from an older version of the file");
+        }
+        Self::decode(body)
+    }
+
+    fn decode(body: &[u8]) -> Result<Dialogue> {
+        let mut dialogue: Dialogue = ciborium::de::from_reader(body)?;
+        dialogue.graph = SectionGraph::new(&dialogue.passage_groups)?;
+        dialogue.graph.link(&dialogue.passage_groups);
+        Ok(dialogue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = br#"
+        externs = ["player_name"]
+        [variables]
+        gold = 10
+        name = ""
+        [[section]]
+        id = "start"
+        speaker = "Merchant"
+        passages = [
+            """Hello, {player_name}. Long time
+            no see.""",
+            { speaker = "Snake", text = "<<await wave>> Hey." },
+        ]
+        on_enter = ["play_music \"market theme\" loud"]
+        choices = [
+            { label = "Buy", goto = "buy", if = "gold >= 5 and not seen('buy')" },
+            { label = "Haggle", check = { stat = "charm", dc = 12 }, on_success = "buy", on_failure = "name" },
+            { label = "Leave", say = "See you.", speaker = "Snake" },
+        ]
+        timeout = 5.0
+        [[section]]
+        id = "buy"
+        passages = ["Thanks!"]
+        if = "visits('start') > 0"
+        next = "start"
+        [[section]]
+        id = "name"
+        passages = ["Who are you, anyway?"]
+        input = { variable = "name", max_len = 12, answer = "snake", on_match = "buy" }
+        end = true
+        [bark.greet]
+        lines = [{ text = "Hi!", weight = 2.0 }, { text = "Hello!" }]
+        cooldown = 3.0
+    "#;
+
+    /// The dialogue as it should come back, which is everything but where
+    /// the conditions were in the TOML.
+    fn without_spans(mut dialogue: Dialogue) -> Dialogue {
+        for group in &mut dialogue.passage_groups {
+            group.condition_source = None;
+            for choice in group.choices.iter_mut().flatten() {
+                choice.condition_source = None;
+            }
+        }
+        dialogue
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dialogue = Dialogue::from_slice(SOURCE).unwrap();
+        let compiled = dialogue.to_compiled(SOURCE).unwrap();
+        assert!(compiled.starts_with(MAGIC));
+        let loaded = Dialogue::from_compiled(&compiled).unwrap();
+        assert_eq!(without_spans(dialogue), loaded);
+        assert_eq!(Some(1), loaded.section_index("buy"));
+        assert!(loaded.passage_groups[1].condition.is_some());
+        assert_eq!(
+            loaded,
+            Dialogue::from_compiled_source(&compiled, SOURCE).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_out_of_date() {
+        let compiled = Dialogue::from_slice(SOURCE)
+            .unwrap()
+            .to_compiled(SOURCE)
+            .unwrap();
+        let mut edited = SOURCE.to_vec();
+        edited.extend(b"# edited");
+        let err = Dialogue::from_compiled_source(&compiled, &edited).unwrap_err();
+        assert!(err.to_string().contains("older version"));
+        // Loading it on its own doesn't care where it came from.
+        assert!(Dialogue::from_compiled(&compiled).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(Dialogue::from_compiled(SOURCE).is_err());
+        assert!(Dialogue::from_compiled(b"TLKD\x01").is_err());
+
+        let mut compiled = Dialogue::from_slice(SOURCE)
+            .unwrap()
+            .to_compiled(SOURCE)
+            .unwrap();
+        compiled[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = Dialogue::from_compiled(&compiled).unwrap_err();
+        assert!(err.to_string().contains("version"));
+    }
+}
@@ -0,0 +1,678 @@
+//! Conditions that decide whether a section or choice is offered, such as
+//! `gold >= 10 && has_item("keycard")`.
+//!
+//! Expressions are parsed and type-checked when the dialogue is loaded, so
+//! mistakes show up then rather than halfway through a conversation.
+
+use super::Value;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The kind of value an expression produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    Bool,
+    Number,
+    Text,
+    /// Not known until the game provides it, such as an `extern`.
+    Any,
+}
+
+impl Type {
+    fn of(value: &Value) -> Type {
+        match value {
+            Value::Bool(_) => Type::Bool,
+            Value::Number(_) => Type::Number,
+            Value::Text(_) => Type::Text,
+        }
+    }
+
+    /// Whether a value of this type can be used where `other` is expected.
+    fn fits(self, other: Type) -> bool {
+        self == other || self == Type::Any || other == Type::Any
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Bool => "bool",
+            Type::Number => "number",
+            Type::Text => "text",
+            Type::Any => "any",
+        })
+    }
+}
+
+/// A function the game provides for conditions to call, as declared under
+/// `[functions]`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    #[serde(default)]
+    pub args: Vec<Type>,
+    /// Most functions are predicates, like `has_item("keycard")`.
+    #[serde(default = "Signature::default_returns")]
+    pub returns: Type,
+}
+
+impl Signature {
+    fn default_returns() -> Type {
+        Type::Bool
+    }
+
+    /// Functions every dialogue can call without declaring them.
+    ///
+    /// `visits("id")` is how many times a section has been visited (or a
+    /// choice picked, as `"section.choice"`), and `seen("id")` whether it has
+    /// been at all.
+    fn builtin(name: &str) -> Option<Signature> {
+        let returns = match name {
+            "visits" => Type::Number,
+            "seen" => Type::Bool,
+            _ => return None,
+        };
+        Some(Signature {
+            args: vec![Type::Text],
+            returns,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// Where an expression gets its variables and functions from while it's
+/// being evaluated.
+pub trait Env {
+    fn var(&self, name: &str) -> Option<Value>;
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value>;
+}
+
+/// What an expression can refer to while it's being checked.
+pub struct Scope<'a> {
+    pub variables: &'a HashMap<String, Value>,
+    pub externs: &'a [String],
+    pub functions: &'a HashMap<String, Signature>,
+    /// What `visits` and `seen` can be asked about.
+    pub visit_names: &'a HashSet<String>,
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            bail!("unexpected `{token}` in `{src}`");
+        }
+        Ok(expr)
+    }
+
+    /// Work out what type the expression produces, making sure everything it
+    /// refers to exists and is used sensibly along the way.
+    pub fn check(&self, scope: &Scope) -> Result<Type> {
+        match self {
+            Expr::Literal(value) => Ok(Type::of(value)),
+            Expr::Var(name) => {
+                if let Some(value) = scope.variables.get(name) {
+                    Ok(Type::of(value))
+                } else if scope.externs.contains(name) {
+                    Ok(Type::Any)
+                } else {
+                    bail!("unknown variable `{name}`")
+                }
+            }
+            Expr::Unary(op, inner) => {
+                let expected = match op {
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Neg => Type::Number,
+                };
+                let ty = inner.check(scope)?;
+                if !ty.fits(expected) {
+                    bail!("expected {expected} but found {ty}");
+                }
+                Ok(expected)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.check(scope)?, rhs.check(scope)?);
+                let mismatch = || anyhow!("can't use `{op}` on {lhs} and {rhs}");
+                match op {
+                    BinaryOp::Or | BinaryOp::And => {
+                        if !(lhs.fits(Type::Bool) && rhs.fits(Type::Bool)) {
+                            return Err(mismatch());
+                        }
+                        Ok(Type::Bool)
+                    }
+                    BinaryOp::Eq | BinaryOp::Ne => {
+                        if !lhs.fits(rhs) {
+                            return Err(mismatch());
+                        }
+                        Ok(Type::Bool)
+                    }
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+                        if !(lhs.fits(rhs) && lhs != Type::Bool && rhs != Type::Bool) {
+                            return Err(mismatch());
+                        }
+                        Ok(Type::Bool)
+                    }
+                    // Joins text together as well as adding numbers.
+                    BinaryOp::Add => {
+                        if !(lhs.fits(rhs) && lhs != Type::Bool && rhs != Type::Bool) {
+                            return Err(mismatch());
+                        }
+                        Ok(if lhs == Type::Any { rhs } else { lhs })
+                    }
+                    BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                        if !(lhs.fits(Type::Number) && rhs.fits(Type::Number)) {
+                            return Err(mismatch());
+                        }
+                        Ok(Type::Number)
+                    }
+                }
+            }
+            Expr::Call(name, args) => {
+                let Some(signature) = scope
+                    .functions
+                    .get(name)
+                    .cloned()
+                    .or_else(|| Signature::builtin(name))
+                else {
+                    bail!("unknown function `{name}`");
+                };
+                if let (Some(_), [Expr::Literal(Value::Text(visited))]) =
+                    (Signature::builtin(name), args.as_slice())
+                {
+                    if !scope.visit_names.contains(visited) {
+                        bail!("`{name}` can't find a section or choice called `{visited}`");
+                    }
+                }
+                if args.len() != signature.args.len() {
+                    bail!(
+                        "`{name}` takes {} arguments but was given {}",
+                        signature.args.len(),
+                        args.len()
+                    );
+                }
+                for (idx, (arg, expected)) in args.iter().zip(&signature.args).enumerate() {
+                    let ty = arg.check(scope)?;
+                    if !ty.fits(*expected) {
+                        bail!(
+                            "argument {} of `{name}` should be {expected} but is {ty}",
+                            idx + 1
+                        );
+                    }
+                }
+                Ok(signature.returns)
+            }
+        }
+    }
+
+    pub fn eval(&self, env: &dyn Env) -> Result<Value> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Var(name) => env
+                .var(name)
+                .ok_or_else(|| anyhow!("no value for `{name}`")),
+            Expr::Unary(op, inner) => match (op, inner.eval(env)?) {
+                (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (UnaryOp::Neg, Value::Number(n)) => Ok(Value::Number(-n)),
+                (_, value) => bail!("can't use {op:?} on `{value}`"),
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                // Only look at the right-hand side when it matters.
+                let lhs = lhs.eval(env)?;
+                match (op, &lhs) {
+                    (BinaryOp::Or, Value::Bool(true)) => return Ok(lhs),
+                    (BinaryOp::And, Value::Bool(false)) => return Ok(lhs),
+                    _ => {}
+                }
+                binary(*op, lhs, rhs.eval(env)?)
+            }
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(env))
+                    .collect::<Result<Vec<_>>>()?;
+                env.call(name, &args)
+                    .ok_or_else(|| anyhow!("no function `{name}`"))
+            }
+        }
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value> {
+    use Value::*;
+    Ok(match (op, lhs, rhs) {
+        (BinaryOp::Or | BinaryOp::And, Bool(_), Bool(b)) => Bool(b),
+        (BinaryOp::Eq, a, b) => Bool(a == b),
+        (BinaryOp::Ne, a, b) => Bool(a != b),
+        (BinaryOp::Lt, Number(a), Number(b)) => Bool(a < b),
+        (BinaryOp::Le, Number(a), Number(b)) => Bool(a <= b),
+        (BinaryOp::Gt, Number(a), Number(b)) => Bool(a > b),
+        (BinaryOp::Ge, Number(a), Number(b)) => Bool(a >= b),
+        (BinaryOp::Lt, Text(a), Text(b)) => Bool(a < b),
+        (BinaryOp::Le, Text(a), Text(b)) => Bool(a <= b),
+        (BinaryOp::Gt, Text(a), Text(b)) => Bool(a > b),
+        (BinaryOp::Ge, Text(a), Text(b)) => Bool(a >= b),
+        (BinaryOp::Add, Number(a), Number(b)) => Number(a + b),
+        (BinaryOp::Add, Text(a), Text(b)) => Text(a + &b),
+        (BinaryOp::Sub, Number(a), Number(b)) => Number(a - b),
+        (BinaryOp::Mul, Number(a), Number(b)) => Number(a * b),
+        (BinaryOp::Div, Number(a), Number(b)) => Number(a / b),
+        (BinaryOp::Rem, Number(a), Number(b)) => Number(a % b),
+        (op, a, b) => bail!("can't use `{op}` on `{a}` and `{b}`"),
+    })
+}
+
+/// A parsed `if` condition, along with the text it came from.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub source: String,
+    pub expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition> {
+        Ok(Condition {
+            source: source.to_string(),
+            expr: Expr::parse(source)?,
+        })
+    }
+
+    /// Make sure the condition only refers to things that exist, and works
+    /// out to true or false.
+    pub fn check(&self, scope: &Scope) -> Result<()> {
+        let ty = self.expr.check(scope)?;
+        if !ty.fits(Type::Bool) {
+            bail!("condition `{}` gives {ty}, not bool", self.source);
+        }
+        Ok(())
+    }
+
+    /// Evaluate the condition, treating anything that goes wrong as false.
+    pub fn test(&self, env: &dyn Env) -> bool {
+        match self.expr.eval(env) {
+            Ok(Value::Bool(b)) => b,
+            Ok(value) => {
+                eprintln!("Condition `{}` gave `{value}`, not a bool.", self.source);
+                false
+            }
+            Err(e) => {
+                eprintln!("Condition `{}` failed: {e}", self.source);
+                false
+            }
+        }
+    }
+}
+
+impl PartialEq for Condition {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Condition {}
+
+/// Conditions are stored as their source, as in compiled dialogue.
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+/// Reading a condition back only parses it. It's up to whoever wrote it out
+/// to have checked it first.
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Condition::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Text(s) => write!(f, "{s:?}"),
+            Token::Ident(s) => f.write_str(s),
+            Token::Symbol(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Longest first, so `<=` isn't read as `<` followed by `=`.
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", ",",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = src.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| anyhow!("bad number `{}` in `{src}`", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c == '"' || c == '\'' {
+            let Some(end) = rest[1..].find(c) else {
+                bail!("unclosed quote in `{src}`");
+            };
+            tokens.push(Token::Text(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            bail!("unexpected `{c}` in `{src}`");
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent, one method per level of precedence from loosest to
+/// tightest.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Move past the next token if it's one of `ops`, written either as a
+    /// symbol or a word.
+    fn eat(&mut self, ops: &[&str]) -> Option<&'static str> {
+        let found = match self.peek()? {
+            Token::Symbol(s) => ops.iter().find(|op| *op == s).map(|_| *s),
+            Token::Ident(word) => match word.as_str() {
+                "or" if ops.contains(&"||") => Some("||"),
+                "and" if ops.contains(&"&&") => Some("&&"),
+                "not" if ops.contains(&"!") => Some("!"),
+                _ => None,
+            },
+            _ => None,
+        }?;
+        self.pos += 1;
+        Some(found)
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => bail!("expected `{symbol}` but found `{token}`"),
+            None => bail!("expected `{symbol}` but the condition ended"),
+        }
+    }
+
+    fn binary_level(&mut self, ops: &[&str], next: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        let mut lhs = next(self)?;
+        while let Some(op) = self.eat(ops) {
+            let op = match op {
+                "||" => BinaryOp::Or,
+                "&&" => BinaryOp::And,
+                "==" => BinaryOp::Eq,
+                "!=" => BinaryOp::Ne,
+                "<" => BinaryOp::Lt,
+                "<=" => BinaryOp::Le,
+                ">" => BinaryOp::Gt,
+                ">=" => BinaryOp::Ge,
+                "+" => BinaryOp::Add,
+                "-" => BinaryOp::Sub,
+                "*" => BinaryOp::Mul,
+                "/" => BinaryOp::Div,
+                _ => BinaryOp::Rem,
+            };
+            let rhs = next(self)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        self.binary_level(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        self.binary_level(&["&&"], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        self.binary_level(&["==", "!=", "<=", ">=", "<", ">"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        self.binary_level(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        self.binary_level(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.eat(&["!", "-"]) {
+            Some("!") => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Some(_) => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            bail!("the condition ended too soon");
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Literal(Value::Number(n))),
+            Token::Text(s) => Ok(Expr::Literal(Value::Text(s))),
+            Token::Ident(word) if word == "true" => Ok(Expr::Literal(Value::Bool(true))),
+            Token::Ident(word) if word == "false" => Ok(Expr::Literal(Value::Bool(false))),
+            Token::Ident(name) => {
+                if self.peek() != Some(&Token::Symbol("(")) {
+                    return Ok(Expr::Var(name));
+                }
+                self.pos += 1;
+                let mut args = vec![];
+                if self.peek() == Some(&Token::Symbol(")")) {
+                    self.pos += 1;
+                    return Ok(Expr::Call(name, args));
+                }
+                loop {
+                    args.push(self.or()?);
+                    if self.peek() == Some(&Token::Symbol(",")) {
+                        self.pos += 1;
+                    } else {
+                        self.expect(")")?;
+                        return Ok(Expr::Call(name, args));
+                    }
+                }
+            }
+            Token::Symbol("(") => {
+                let inner = self.or()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Symbol(s) => bail!("unexpected `{s}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestEnv;
+
+    impl Env for TestEnv {
+        fn var(&self, name: &str) -> Option<Value> {
+            match name {
+                "gold" => Some(12.into()),
+                "name" => Some("Snake".into()),
+                _ => None,
+            }
+        }
+
+        fn call(&self, name: &str, args: &[Value]) -> Option<Value> {
+            match (name, args) {
+                ("has_item", [Value::Text(item)]) => Some((item == "keycard").into()),
+                _ => None,
+            }
+        }
+    }
+
+    fn eval(src: &str) -> Value {
+        Expr::parse(src).unwrap().eval(&TestEnv).unwrap()
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(Value::Number(7.0), eval("1 + 2 * 3"));
+        assert_eq!(Value::Number(9.0), eval("(1 + 2) * 3"));
+        assert_eq!(Value::Number(-1.0), eval("-3 + 2"));
+        assert_eq!(Value::Bool(true), eval("1 < 2 && 2 < 3 || false"));
+        assert_eq!(Value::Bool(false), eval("not (true or false)"));
+    }
+
+    #[test]
+    fn test_variables_and_calls() {
+        assert_eq!(
+            Value::Bool(true),
+            eval("gold >= 10 && has_item(\"keycard\")")
+        );
+        assert_eq!(Value::Bool(false), eval("has_item('map')"));
+        assert_eq!(Value::Text("Solid Snake".into()), eval("'Solid ' + name"));
+        assert_eq!(Value::Bool(true), eval("gold % 5 == 2"));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        // `missing` has no value, but is never looked at.
+        assert_eq!(Value::Bool(true), eval("true || missing"));
+        assert!(Expr::parse("false || missing")
+            .unwrap()
+            .eval(&TestEnv)
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for src in ["", "1 +", "(1", "f(1,", "1 2", "a # b", "'open"] {
+            assert!(Expr::parse(src).is_err(), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let variables = HashMap::from([
+            ("gold".to_string(), Value::Number(0.0)),
+            ("met".to_string(), Value::Bool(false)),
+        ]);
+        let externs = vec!["player_name".to_string()];
+        let functions = HashMap::from([(
+            "has_item".to_string(),
+            Signature {
+                args: vec![Type::Text],
+                returns: Type::Bool,
+            },
+        )]);
+        let visit_names = HashSet::from(["intro".to_string()]);
+        let scope = Scope {
+            variables: &variables,
+            externs: &externs,
+            functions: &functions,
+            visit_names: &visit_names,
+        };
+        let check = |src| Expr::parse(src).unwrap().check(&scope);
+
+        assert_eq!(Type::Bool, check("gold > 3 && !met").unwrap());
+        assert_eq!(Type::Bool, check("player_name == 'Snake'").unwrap());
+        assert_eq!(Type::Number, check("gold * 2").unwrap());
+        assert_eq!(Type::Bool, check("has_item('keycard')").unwrap());
+        assert_eq!(Type::Bool, check("visits('intro') > 1").unwrap());
+        assert_eq!(Type::Bool, check("seen('intro')").unwrap());
+
+        assert!(check("gold && met").is_err());
+        assert!(check("gold == 'lots'").is_err());
+        assert!(check("silver > 3").is_err());
+        assert!(check("has_item(3)").is_err());
+        assert!(check("has_item()").is_err());
+        assert!(check("is_raining()").is_err());
+        assert!(check("seen('outro')").is_err());
+    }
+}
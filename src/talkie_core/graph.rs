@@ -0,0 +1,223 @@
+//! How the sections of a dialogue lead into one another.
+//!
+//! Worked out once when a dialogue is loaded, so jumps don't need to search
+//! through every section to find where they're going.
+
+use super::{split_target, PassageGroup, RETURN_TARGET};
+use anyhow::{bail, Result};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SectionGraph {
+    /// Section indices, by id.
+    ids: HashMap<String, usize>,
+    /// The sections in the same file each section can lead to.
+    edges: Vec<Vec<usize>>,
+}
+
+impl SectionGraph {
+    /// Index the sections by id, making sure no two share one.
+    ///
+    /// Edges are left for `link()`, since they can only be found once every
+    /// target is known to be good.
+    pub(super) fn new(groups: &[PassageGroup]) -> Result<Self> {
+        let mut ids = HashMap::with_capacity(groups.len());
+        for (idx, group) in groups.iter().enumerate() {
+            if let Some(id) = &group.id {
+                if let Some(first) = ids.insert(id.clone(), idx) {
+                    bail!("sections {first} and {idx} both have the id `{id}`");
+                }
+            }
+        }
+        Ok(SectionGraph { ids, edges: vec![] })
+    }
+
+    /// Work out where each section can lead.
+    ///
+    /// Jumps to other files and returns aren't included, since where they end
+    /// up depends on more than this file.
+    pub(super) fn link(&mut self, groups: &[PassageGroup]) {
+        self.edges = groups
+            .iter()
+            .enumerate()
+            .map(|(idx, group)| {
+                let choices = group.choices.iter().flatten();
+                let calls = choices.clone().any(|choice| choice.call.is_some());
+                let targets = choices.flat_map(|choice| {
+                    let outcomes = choice.on_success.iter().chain(&choice.on_failure);
+                    choice.goto.iter().chain(&choice.call).chain(outcomes)
+                });
+                let inputs = group
+                    .input
+                    .iter()
+                    .flat_map(|input| input.on_match.iter().chain(&input.on_mismatch));
+                let next = group.next.iter().filter(|&next| next != RETURN_TARGET);
+                let mut edges: Vec<usize> = targets
+                    .chain(inputs)
+                    .chain(next)
+                    .filter_map(|target| match split_target(target) {
+                        (None, id) => self.index(id),
+                        (Some(_), _) => None,
+                    })
+                    .collect();
+
+                // Sections carry on to the one after them (wrapping around at
+                // the end) unless told otherwise, and calls come back there.
+                // One whose condition fails is passed over for it too.
+                if (group.next.is_none() && !group.end) || calls || group.condition.is_some() {
+                    edges.push((idx + 1) % groups.len());
+                }
+                edges.sort_unstable();
+                edges.dedup();
+                edges
+            })
+            .collect();
+    }
+
+    /// The index of the section with the given id.
+    pub fn index(&self, id: &str) -> Option<usize> {
+        self.ids.get(id).copied()
+    }
+
+    /// The sections in the same file that `section` can lead to directly.
+    pub fn successors(&self, section: usize) -> &[usize] {
+        self.edges.get(section).map_or(&[], Vec::as_slice)
+    }
+
+    /// Which sections can be reached from any of `roots`, by index.
+    pub fn reachable(&self, roots: impl IntoIterator<Item = usize>) -> Vec<bool> {
+        let mut seen = vec![false; self.edges.len()];
+        let mut queue: VecDeque<usize> = roots.into_iter().collect();
+        while let Some(section) = queue.pop_front() {
+            if std::mem::replace(&mut seen[section], true) {
+                continue;
+            }
+            queue.extend(self.successors(section).iter().filter(|&&s| !seen[s]));
+        }
+        seen
+    }
+
+    /// Sections nothing can ever get to: not the first section, not anything
+    /// with an id (since code or other files might start there), and not
+    /// anything those lead to.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let roots = std::iter::once(0).chain(self.ids.values().copied());
+        let reachable = self.reachable(roots.filter(|&idx| idx < self.edges.len()));
+        (0..self.edges.len())
+            .filter(|&idx| !reachable[idx])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Dialogue;
+
+    #[test]
+    fn test_successors() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [include]
+            shop = "shop.toml"
+            [[section]]
+            passages = ["Where to?"]
+            choices = [
+                { label = "A", goto = "a" },
+                { label = "Shop", call = "shop#counter" },
+            ]
+            [[section]]
+            id = "a"
+            passages = ["A"]
+            next = "c"
+            [[section]]
+            id = "b"
+            passages = ["B"]
+            end = true
+            [[section]]
+            id = "c"
+            passages = ["C"]
+            "#,
+        )
+        .unwrap();
+        let graph = dialogue.graph();
+        assert_eq!(Some(2), graph.index("b"));
+        assert_eq!(None, graph.index("shop#counter"));
+        // The call comes back to the section after.
+        assert_eq!(&[1], graph.successors(0));
+        assert_eq!(&[3], graph.successors(1));
+        assert!(graph.successors(2).is_empty());
+        // The last section wraps around to the first.
+        assert_eq!(&[0], graph.successors(3));
+        assert_eq!(vec![true, true, false, true], graph.reachable([0]));
+    }
+
+    #[test]
+    fn test_gated_sections_fall_through() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [variables]
+            has_key = false
+            [[section]]
+            if = "!has_key"
+            passages = ["Locked out."]
+            end = true
+            [[section]]
+            if = "has_key"
+            passages = ["In you go."]
+            next = "a"
+            [[section]]
+            passages = ["Skipped over"]
+            [[section]]
+            id = "a"
+            passages = ["A"]
+            end = true
+            "#,
+        )
+        .unwrap();
+        let graph = dialogue.graph();
+        // A section whose condition fails is passed over for the next one.
+        assert_eq!(&[1], graph.successors(0));
+        assert_eq!(&[2, 3], graph.successors(1));
+        assert!(graph.unreachable().is_empty());
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Hello"]
+            next = "a"
+            [[section]]
+            passages = ["Skipped over"]
+            [[section]]
+            id = "a"
+            passages = ["A"]
+            end = true
+            [[section]]
+            passages = ["After the end"]
+            [[section]]
+            id = "b"
+            passages = ["Started from code"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(vec![1, 3], dialogue.graph().unreachable());
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let err = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["One"]
+            [[section]]
+            id = "a"
+            passages = ["Two"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("both have the id `a`"));
+    }
+}
@@ -0,0 +1,1841 @@
+// A lot of this was copied from `talkie/assets/mod.rs` but leaves behind all
+// the amethyst-specific asset-loader support.
+
+use anyhow::{bail, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use toml::Spanned;
+
+mod command;
+mod compiled;
+mod expr;
+mod graph;
+mod text;
+
+pub use command::{extract_commands, Command};
+pub use compiled::EXTENSION as COMPILED_EXTENSION;
+pub use expr::{Condition, Env, Scope, Signature};
+pub use graph::SectionGraph;
+pub use text::{interpolate, placeholders, resolve_sequences, Value};
+
+/// Sections that include one or more choices will present a menu to the player
+/// once all the passage text has been shown. The last passage will be displayed
+/// as the prompt for the choices.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Choice {
+    /// Names the choice for `visits("section.id")`. Without one, the choice
+    /// goes by its position in the list instead.
+    pub id: Option<String>,
+    /// The text to display in the menu.
+    pub label: String,
+    /// The full line to play once the choice is picked, when the label is
+    /// just a paraphrase of it.
+    pub say: Option<String>,
+    /// Who says the `say` line.
+    pub speaker: Option<String>,
+    /// When  specified, this is used as a section (matched by id) to jump to.
+    /// If no goto is listed, the choice simply advances to the section's
+    /// `next`, or else the one that follows it.
+    pub goto: Option<String>,
+    /// Like `goto`, but a later `return` comes back to the section after this
+    /// one.
+    pub call: Option<String>,
+    /// Go back to wherever the last `call` left off.
+    #[serde(default, rename = "return")]
+    pub returns: bool,
+    /// Once picked, the choice is removed from the menu for good.
+    #[serde(default)]
+    pub once: bool,
+    /// Only offered once every other choice in the menu has been used up (by
+    /// way of `once` or `if`).
+    #[serde(default)]
+    pub fallback: bool,
+    /// Only offered while this holds, as in `if = "gold >= 10"`.
+    #[serde(rename = "if", skip_serializing)]
+    condition_source: Option<Spanned<String>>,
+    /// Parsed from `if` when the dialogue is loaded, and kept as is in
+    /// compiled dialogue.
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    /// A roll to make when the choice is picked.
+    pub check: Option<Check>,
+    /// Where to go when the `check` passes, instead of where the choice would
+    /// otherwise lead.
+    pub on_success: Option<String>,
+    /// Where to go when the `check` fails.
+    pub on_failure: Option<String>,
+}
+
+/// A roll of a twenty-sided die plus one of the player's stats, which passes
+/// when it comes to at least the difficulty class.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Check {
+    pub stat: String,
+    pub dc: i32,
+}
+
+impl Check {
+    pub const DIE: i32 = 20;
+
+    /// The chance of passing, from 0 to 1, with the given stat.
+    pub fn odds(&self, stat: i32) -> f32 {
+        let needed = self.dc - stat;
+        (Self::DIE - needed + 1).clamp(0, Self::DIE) as f32 / Self::DIE as f32
+    }
+
+    /// Roll the die, returning what came up and whether the check passed.
+    pub fn roll(&self, stat: i32, rng: &mut impl Rng) -> (i32, bool) {
+        let roll = rng.gen_range(1..=Self::DIE);
+        (roll, roll + stat >= self.dc)
+    }
+}
+
+/// Asks the player to type something in once a section's passages are done,
+/// such as a name or a password.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TextInput {
+    /// The variable to store what was typed in. It has to be declared as
+    /// text.
+    pub variable: String,
+    #[serde(default)]
+    pub min_len: usize,
+    pub max_len: Option<usize>,
+    /// The only characters that can be typed, when set.
+    pub allowed: Option<String>,
+    /// What the player is meant to type, for `on_match` and `on_mismatch`.
+    pub answer: Option<String>,
+    #[serde(default)]
+    pub ignore_case: bool,
+    pub on_match: Option<String>,
+    pub on_mismatch: Option<String>,
+}
+
+impl TextInput {
+    /// Whether `c` can be typed after what's been typed so far.
+    pub fn accepts(&self, typed: &str, c: char) -> bool {
+        let room = !matches!(self.max_len, Some(max) if typed.chars().count() >= max);
+        let allowed = match &self.allowed {
+            Some(allowed) => allowed.contains(c),
+            None => !c.is_control(),
+        };
+        room && allowed
+    }
+
+    /// Whether the player could type all of `text` and have it accepted,
+    /// swapping the case of any letters that can't be typed as they are when
+    /// case is ignored.
+    pub fn can_type(&self, text: &str) -> bool {
+        let mut typed = String::new();
+        for c in text.chars() {
+            let mut forms = vec![c];
+            if self.ignore_case {
+                forms.extend(c.to_lowercase().chain(c.to_uppercase()));
+            }
+            match forms.into_iter().find(|&c| self.accepts(&typed, c)) {
+                Some(c) => typed.push(c),
+                None => return false,
+            }
+        }
+        self.validate(&typed).is_ok()
+    }
+
+    /// Check the finished text, explaining what's wrong with it if anything.
+    pub fn validate(&self, typed: &str) -> Result<()> {
+        if typed.trim().chars().count() < self.min_len.max(1) {
+            match self.min_len {
+                0 | 1 => bail!("Type something first."),
+                len => bail!("Needs at least {len} characters."),
+            }
+        }
+        Ok(())
+    }
+
+    /// Where to go once `typed` has been entered, or `None` to carry on as
+    /// the section would.
+    pub fn jump(&self, typed: &str) -> Option<Jump> {
+        let answer = self.answer.as_deref()?;
+        let typed = typed.trim();
+        let matched = if self.ignore_case {
+            typed.to_lowercase() == answer.to_lowercase()
+        } else {
+            typed == answer
+        };
+        let target = if matched {
+            &self.on_match
+        } else {
+            &self.on_mismatch
+        };
+        target.clone().map(Jump::Goto)
+    }
+}
+
+/// Where to go once a choice has been picked, or a section is over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Jump {
+    Goto(String),
+    Call(String),
+    Return,
+    /// Stop the conversation.
+    End,
+}
+
+/// Written as a section's `next` to go back to wherever the last `call` left
+/// off.
+pub const RETURN_TARGET: &str = "$return";
+
+impl Choice {
+    /// Where picking this choice leads, or `None` to carry on to the next
+    /// section.
+    pub fn jump(&self) -> Option<Jump> {
+        if let Some(id) = &self.goto {
+            Some(Jump::Goto(id.clone()))
+        } else if let Some(id) = &self.call {
+            Some(Jump::Call(id.clone()))
+        } else if self.returns {
+            Some(Jump::Return)
+        } else {
+            None
+        }
+    }
+
+    /// Where picking this choice leads once its `check` has been rolled.
+    pub fn jump_after_check(&self, passed: bool) -> Option<Jump> {
+        let target = if passed {
+            &self.on_success
+        } else {
+            &self.on_failure
+        };
+        match target {
+            Some(id) => Some(Jump::Goto(id.clone())),
+            None => self.jump(),
+        }
+    }
+}
+
+/// Work out which choices should be offered in a menu.
+///
+/// `is_taken` reports whether the choice at a given index has been picked
+/// before, and `is_allowed` whether its condition holds. The return value is
+/// the indices of the choices to show, in order.
+///
+/// Choices marked `once` are dropped after they've been taken. Fallback choices
+/// are held back until there's nothing else left to offer.
+pub fn visible_choices(
+    choices: &[Choice],
+    is_taken: impl Fn(usize) -> bool,
+    is_allowed: impl Fn(usize) -> bool,
+) -> Vec<usize> {
+    let remaining: Vec<usize> = (0..choices.len())
+        .filter(|&idx| !(choices[idx].once && is_taken(idx)) && is_allowed(idx))
+        .collect();
+
+    let regular: Vec<usize> = remaining
+        .iter()
+        .copied()
+        .filter(|&idx| !choices[idx].fallback)
+        .collect();
+
+    if regular.is_empty() {
+        remaining
+    } else {
+        regular
+    }
+}
+
+/// How a section picks which of its passages to show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassageMode {
+    /// Show every passage, in order.
+    #[default]
+    Sequence,
+    /// Show one passage, picked at random.
+    Random,
+    /// Show one passage, moving on to the next one each visit.
+    Cycle,
+    /// Show one passage, at random, but don't repeat any until they've all
+    /// been shown.
+    Shuffle,
+}
+
+/// How many times each section has been visited and each choice picked, by
+/// the names `visits` and `seen` know them by.
+///
+/// Only sections with an id (and the choices within them) are counted.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VisitCounts(HashMap<String, u32>);
+
+impl VisitCounts {
+    pub fn visit(&mut self, name: impl Into<String>) {
+        *self.0.entry(name.into()).or_default() += 1;
+    }
+
+    pub fn get(&self, name: &str) -> u32 {
+        self.0.get(name).copied().unwrap_or_default()
+    }
+
+    /// Look up a built-in value, like `visits.intro` or `seen.intro`.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        if let Some(name) = name.strip_prefix("visits.") {
+            Some(f64::from(self.get(name)).into())
+        } else {
+            name.strip_prefix("seen.")
+                .map(|name| (self.get(name) > 0).into())
+        }
+    }
+}
+
+/// Keeps track of which variant to pick next for a section or an inline
+/// sequence.
+#[derive(Clone, Debug, Default)]
+pub struct Variations {
+    visits: usize,
+    /// Variants left to pick from before reshuffling, in reverse order.
+    bag: Vec<usize>,
+    last: Option<usize>,
+}
+
+impl Variations {
+    /// Pick one of `count` variants.
+    pub fn next(&mut self, mode: PassageMode, count: usize, rng: &mut impl Rng) -> usize {
+        let visit = self.visits;
+        self.visits += 1;
+        let pick = match mode {
+            PassageMode::Sequence | PassageMode::Cycle => visit % count,
+            PassageMode::Random => rng.gen_range(0..count),
+            PassageMode::Shuffle => {
+                // The section may have been edited since the bag was filled.
+                self.bag.retain(|&idx| idx < count);
+                if self.bag.is_empty() {
+                    self.bag = (0..count).collect();
+                    self.bag.shuffle(rng);
+                    // Don't let a fresh bag open with what the last one
+                    // ended on.
+                    if count > 1 && self.bag.last().copied() == self.last {
+                        self.bag.swap(0, count - 1);
+                    }
+                }
+                self.bag.pop().unwrap_or_default()
+            }
+        };
+        self.last = Some(pick);
+        pick
+    }
+}
+
+/// A single block of text, written either as a plain string or as a table
+/// when someone other than the section's `speaker` says it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "PassageSource")]
+pub struct Passage {
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PassageSource {
+    Text(String),
+    Line {
+        speaker: Option<String>,
+        text: String,
+    },
+}
+
+impl From<PassageSource> for Passage {
+    fn from(source: PassageSource) -> Self {
+        match source {
+            PassageSource::Text(text) => Passage {
+                speaker: None,
+                text,
+            },
+            PassageSource::Line { speaker, text } => Passage { speaker, text },
+        }
+    }
+}
+
+/// A sequence of passages, associated with a speaker.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PassageGroup {
+    /// This optional id is how `Choice`s find the passage group to jump to when
+    /// a value for `goto` is set.
+    pub id: Option<String>,
+    pub speaker: Option<String>,
+    /// Blocks of text to show, one by one.
+    pub passages: Vec<Passage>,
+    /// Optional audio clips to play alongside each passage, matched up by
+    /// position.
+    pub voices: Option<Vec<String>>,
+    pub choices: Option<Vec<Choice>>,
+    /// When set, the choice menu picks the `default` choice automatically once
+    /// this many seconds have passed.
+    pub timeout: Option<f32>,
+    /// Index of the choice to start with the cursor on, and to pick when the
+    /// `timeout` runs out. Defaults to the first choice. When it's been left
+    /// out of the menu by `if` or `once`, there's no timeout.
+    pub default: Option<usize>,
+    /// Whether to show every passage or pick one of them each visit.
+    #[serde(default)]
+    pub mode: PassageMode,
+    /// Commands to run when the section starts.
+    #[serde(default)]
+    pub on_enter: Vec<Command>,
+    /// Commands to run once the section's passages have all been shown,
+    /// before any choices.
+    #[serde(default)]
+    pub on_exit: Vec<Command>,
+    /// The section to go to afterwards, instead of the one that follows. A
+    /// choice with a jump of its own still takes precedence.
+    pub next: Option<String>,
+    /// Stop the conversation once this section is over.
+    #[serde(default)]
+    pub end: bool,
+    /// Something for the player to type in after the last passage, instead
+    /// of picking from choices.
+    pub input: Option<TextInput>,
+    /// The section is skipped over unless this holds.
+    #[serde(rename = "if", skip_serializing)]
+    condition_source: Option<Spanned<String>>,
+    /// Parsed from `if` when the dialogue is loaded, and kept as is in
+    /// compiled dialogue.
+    #[serde(default)]
+    pub condition: Option<Condition>,
+}
+
+impl PassageGroup {
+    /// The name a choice's visits are counted under, if the section has an
+    /// id.
+    pub fn choice_visit_name(&self, choice: usize) -> Option<String> {
+        let id = self.id.as_ref()?;
+        let choices = self.choices.as_ref()?;
+        let name = match &choices[choice].id {
+            Some(name) => name.clone(),
+            None => choice.to_string(),
+        };
+        Some(format!("{id}.{name}"))
+    }
+
+    /// Where to go once the section is over, or `None` to carry on to the
+    /// next section.
+    pub fn jump(&self) -> Option<Jump> {
+        if self.end {
+            Some(Jump::End)
+        } else {
+            match self.next.as_deref() {
+                Some(RETURN_TARGET) => Some(Jump::Return),
+                Some(id) => Some(Jump::Goto(id.to_string())),
+                None => None,
+            }
+        }
+    }
+
+    /// The path to the voice clip for the given passage, if there is one.
+    pub fn voice(&self, passage: usize) -> Option<&str> {
+        self.voices.as_ref()?.get(passage).map(String::as_str)
+    }
+
+    /// Who says the given passage.
+    pub fn speaker_for(&self, passage: usize) -> Option<&str> {
+        self.passages[passage]
+            .speaker
+            .as_deref()
+            .or(self.speaker.as_deref())
+    }
+
+    /// How many passages are shown on each visit to the section.
+    pub fn visit_len(&self) -> usize {
+        match self.mode {
+            PassageMode::Sequence => self.passages.len(),
+            _ => self.passages.len().min(1),
+        }
+    }
+}
+
+/// A one-liner an entity can say in passing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BarkLine {
+    pub text: String,
+    /// How likely this line is to be picked relative to the rest of the pool.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    /// Seconds before this line can be picked again.
+    pub cooldown: Option<f32>,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// The lines that can be said for a given trigger tag.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BarkPool {
+    pub lines: Vec<BarkLine>,
+    /// Seconds after any line from the pool is said before the pool can be
+    /// used again.
+    pub cooldown: Option<f32>,
+    /// Don't repeat any of the last this-many lines.
+    #[serde(default)]
+    pub no_repeat: usize,
+}
+
+/// What's been said from a `BarkPool` so far, used to enforce its cooldowns.
+#[derive(Clone, Debug, Default)]
+pub struct BarkHistory {
+    pool_ready_at: f64,
+    line_ready_at: HashMap<usize, f64>,
+    recent: VecDeque<usize>,
+}
+
+impl BarkPool {
+    /// Pick a line to say at time `now` (in seconds), recording it in
+    /// `history`.
+    ///
+    /// Returns `None` when the pool or every line in it is cooling down.
+    pub fn pick(&self, history: &mut BarkHistory, now: f64, rng: &mut impl Rng) -> Option<usize> {
+        if now < history.pool_ready_at {
+            return None;
+        }
+        let ready: Vec<usize> = (0..self.lines.len())
+            .filter(|idx| !matches!(history.line_ready_at.get(idx), Some(&at) if now < at))
+            .collect();
+
+        // Always leave at least one line to pick from, so a pool with fewer
+        // lines than `no_repeat` doesn't go quiet.
+        let window = self.no_repeat.min(ready.len().saturating_sub(1));
+        let fresh: Vec<usize> = ready
+            .iter()
+            .copied()
+            .filter(|idx| !history.recent.iter().rev().take(window).any(|x| x == idx))
+            .collect();
+        let candidates = if fresh.is_empty() { ready } else { fresh };
+
+        let idx = *candidates
+            .choose_weighted(rng, |&idx| self.lines[idx].weight)
+            .ok()?;
+
+        history.pool_ready_at = now + f64::from(self.cooldown.unwrap_or_default());
+        if let Some(cooldown) = self.lines[idx].cooldown {
+            history.line_ready_at.insert(idx, now + f64::from(cooldown));
+        }
+        history.recent.push_back(idx);
+        while history.recent.len() > self.no_repeat {
+            history.recent.pop_front();
+        }
+        Some(idx)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Dialogue {
+    /// Can be left out of files that only hold barks.
+    #[serde(default, rename = "section")]
+    pub passage_groups: Vec<PassageGroup>,
+    /// Pools of one-liners, keyed by the tag that triggers them.
+    #[serde(default, rename = "bark")]
+    pub barks: HashMap<String, BarkPool>,
+    /// Variables the dialogue uses, along with their starting values.
+    #[serde(default)]
+    pub variables: HashMap<String, Value>,
+    /// Names of values the game provides for placeholders.
+    #[serde(default)]
+    pub externs: Vec<String>,
+    /// Other dialogue files, by the name used to refer to them in `goto` and
+    /// `call`, as in `goto = "shop#greeting"`.
+    ///
+    /// Files can also be referred to by path without being listed here.
+    #[serde(default)]
+    pub include: HashMap<String, String>,
+    /// Functions the game provides for conditions to call, such as
+    /// `has_item = { args = ["text"] }`.
+    #[serde(default)]
+    pub functions: HashMap<String, Signature>,
+    #[serde(skip)]
+    graph: SectionGraph,
+}
+
+/// Parse and check an `if` condition, pointing at the line it's on if
+/// anything's wrong with it.
+fn parse_condition(
+    bytes: &[u8],
+    source: Option<&Spanned<String>>,
+    scope: &Scope,
+) -> Result<Option<Condition>> {
+    let Some(source) = source else {
+        return Ok(None);
+    };
+    let condition = Condition::parse(source.get_ref()).and_then(|condition| {
+        condition.check(scope)?;
+        Ok(condition)
+    });
+    match condition {
+        Ok(condition) => Ok(Some(condition)),
+        Err(e) => bail!("line {}: {e}", line_of(bytes, source.start())),
+    }
+}
+
+/// The line number (counting from 1) of a position in the source.
+fn line_of(bytes: &[u8], pos: usize) -> usize {
+    bytes[..pos.min(bytes.len())]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1
+}
+
+/// Split a `goto` or `call` target into the file it points to (if it's in
+/// another file) and the id of the section.
+///
+/// An empty section id, as in `"shop.toml#"`, means the first section.
+pub fn split_target(target: &str) -> (Option<&str>, &str) {
+    match target.split_once('#') {
+        Some((module, id)) => (Some(module), id),
+        None => (None, target),
+    }
+}
+
+impl Dialogue {
+    pub fn from_slice(bytes: &[u8]) -> Result<Dialogue> {
+        let mut dialogue: Dialogue = toml::from_slice(bytes)?;
+        dialogue.graph = SectionGraph::new(&dialogue.passage_groups)?;
+        let visit_names = dialogue.visit_names();
+        let scope = Scope {
+            variables: &dialogue.variables,
+            externs: &dialogue.externs,
+            functions: &dialogue.functions,
+            visit_names: &visit_names,
+        };
+        for group in &mut dialogue.passage_groups {
+            group.condition = parse_condition(bytes, group.condition_source.as_ref(), &scope)?;
+            for choice in group.choices.iter_mut().flatten() {
+                choice.condition =
+                    parse_condition(bytes, choice.condition_source.as_ref(), &scope)?;
+            }
+        }
+        for (idx, group) in dialogue.passage_groups.iter().enumerate() {
+            if group.passages.is_empty() {
+                bail!("section {idx} has no passages");
+            }
+            let voice_count = group.voices.as_ref().map(Vec::len).unwrap_or_default();
+            if voice_count > group.passages.len() {
+                bail!(
+                    "section {idx} has {voice_count} voices but only {} passages",
+                    group.passages.len()
+                );
+            }
+            let choice_count = group.choices.as_ref().map(Vec::len).unwrap_or_default();
+            if (group.timeout.is_some() || group.default.is_some()) && choice_count == 0 {
+                bail!("section {idx} has a timeout or default but no choices");
+            }
+            if let Some(default) = group.default {
+                if default >= choice_count {
+                    bail!("section {idx} has default choice {default} but only {choice_count} choices");
+                }
+            }
+            if matches!(group.timeout, Some(secs) if !(secs.is_finite() && secs > 0.0)) {
+                bail!("section {idx} has a timeout that isn't a positive number");
+            }
+            let labels = group.choices.iter().flatten().map(|c| &c.label);
+            let lines = group.choices.iter().flatten().flat_map(|c| &c.say);
+            let texts = group.passages.iter().map(|p| &p.text);
+            for text in texts.chain(labels).chain(lines.clone()) {
+                if let Err(e) = dialogue.check_placeholders(text, &visit_names) {
+                    bail!("section {idx}: {e}");
+                }
+            }
+            for choice in group.choices.iter().flatten() {
+                let jumps = [choice.goto.is_some(), choice.call.is_some(), choice.returns];
+                if jumps.into_iter().filter(|&x| x).count() > 1 {
+                    bail!("section {idx} has a choice with more than one of goto, call and return");
+                }
+                let outcomes = choice.on_success.iter().chain(&choice.on_failure);
+                if choice.check.is_none() && outcomes.clone().next().is_some() {
+                    bail!("section {idx} has a choice with on_success or on_failure but no check");
+                }
+                for target in choice.goto.iter().chain(&choice.call).chain(outcomes) {
+                    if let Err(e) = dialogue.check_target(target) {
+                        bail!("section {idx} has a choice leading to {e}");
+                    }
+                }
+            }
+            if let Some(input) = &group.input {
+                if choice_count > 0 {
+                    bail!("section {idx} has both an input and choices");
+                }
+                if !matches!(
+                    dialogue.variables.get(&input.variable),
+                    Some(Value::Text(_))
+                ) {
+                    bail!(
+                        "section {idx} stores its input in `{}`, which isn't declared as text",
+                        input.variable
+                    );
+                }
+                if matches!(input.max_len, Some(max) if max < input.min_len) {
+                    bail!("section {idx} has an input with max_len less than min_len");
+                }
+                let outcomes = input.on_match.iter().chain(&input.on_mismatch);
+                if input.answer.is_none() && outcomes.clone().next().is_some() {
+                    bail!("section {idx} has an input with on_match or on_mismatch but no answer");
+                }
+                if let Some(answer) = input.answer.as_deref() {
+                    if !input.can_type(answer) {
+                        bail!("section {idx} has an input whose answer `{answer}` can't be typed in, given its length and allowed characters");
+                    }
+                }
+                for target in outcomes {
+                    if let Err(e) = dialogue.check_target(target) {
+                        bail!("section {idx} has an input leading to {e}");
+                    }
+                }
+            }
+            if group.end && group.next.is_some() {
+                bail!("section {idx} has both next and end");
+            }
+            if let Some(target) = group.next.as_deref() {
+                if target != RETURN_TARGET {
+                    if let Err(e) = dialogue.check_target(target) {
+                        bail!("section {idx} has next leading to {e}");
+                    }
+                }
+            }
+            for choice in group.choices.iter().flatten() {
+                if choice.speaker.is_some() && choice.say.is_none() {
+                    bail!("section {idx} has a choice with a speaker but nothing to say");
+                }
+            }
+            for text in group.passages.iter().map(|p| &p.text).chain(lines) {
+                if let Err(e) = extract_commands(text) {
+                    bail!("section {idx}: {e}");
+                }
+            }
+        }
+        for (tag, pool) in &dialogue.barks {
+            if pool.lines.is_empty() {
+                bail!("bark `{tag}` has no lines");
+            }
+            if pool
+                .lines
+                .iter()
+                .any(|line| !(line.weight.is_finite() && line.weight > 0.0))
+            {
+                bail!("bark `{tag}` has a line with a weight that isn't positive");
+            }
+            let cooldowns = pool.lines.iter().map(|line| line.cooldown);
+            if std::iter::once(pool.cooldown)
+                .chain(cooldowns)
+                .any(|cooldown| matches!(cooldown, Some(secs) if secs < 0.0))
+            {
+                bail!("bark `{tag}` has a negative cooldown");
+            }
+            for line in &pool.lines {
+                if let Err(e) = dialogue.check_placeholders(&line.text, &visit_names) {
+                    bail!("bark `{tag}`: {e}");
+                }
+            }
+        }
+        for passage in dialogue
+            .passage_groups
+            .iter_mut()
+            .flat_map(|x| x.passages.iter_mut())
+        {
+            passage.text = reflow_text(&passage.text);
+        }
+        for line in dialogue
+            .passage_groups
+            .iter_mut()
+            .flat_map(|group| group.choices.iter_mut().flatten())
+            .flat_map(|choice| &mut choice.say)
+        {
+            *line = reflow_text(line);
+        }
+        for line in dialogue
+            .barks
+            .values_mut()
+            .flat_map(|pool| pool.lines.iter_mut())
+        {
+            line.text = reflow_text(&line.text).trim_end().to_string();
+        }
+        dialogue.graph.link(&dialogue.passage_groups);
+
+        Ok(dialogue)
+    }
+
+    /// The path of another dialogue file, relative to this one, given either
+    /// its name in `include` or the path itself.
+    pub fn module_path<'a>(&'a self, module: &'a str) -> Option<&'a str> {
+        match self.include.get(module) {
+            Some(path) => Some(path),
+            None if module.ends_with(".toml") => Some(module),
+            None => None,
+        }
+    }
+
+    /// Every `goto`, `call` and `next` target in the file, including the ones
+    /// in other files.
+    fn targets(&self) -> impl Iterator<Item = &str> {
+        self.passage_groups.iter().flat_map(|group| {
+            let choices = group.choices.iter().flatten();
+            let targets = choices.flat_map(|choice| {
+                let outcomes = choice.on_success.iter().chain(&choice.on_failure);
+                choice.goto.iter().chain(&choice.call).chain(outcomes)
+            });
+            let inputs = group
+                .input
+                .iter()
+                .flat_map(|input| input.on_match.iter().chain(&input.on_mismatch));
+            targets.chain(&group.next).chain(inputs).map(String::as_str)
+        })
+    }
+
+    /// Every other dialogue file this one refers to, by the name it uses for
+    /// them.
+    pub fn modules(&self) -> Vec<&str> {
+        let targets = self.targets().filter_map(|target| split_target(target).0);
+        let mut modules: Vec<&str> = self.include.keys().map(String::as_str).collect();
+        for module in targets {
+            if !modules.contains(&module) {
+                modules.push(module);
+            }
+        }
+        modules
+    }
+
+    /// Make sure every target in `module` points at a section that exists in
+    /// `included`, the file it names. On load that can't be checked until the
+    /// file turns up, so it's left to the build.
+    // Only used by the build script.
+    #[allow(dead_code)]
+    pub fn check_module_targets(&self, module: &str, included: &Dialogue) -> Result<()> {
+        for target in self.targets() {
+            let (Some(name), id) = split_target(target) else {
+                continue;
+            };
+            if name != module {
+                continue;
+            }
+            let found = if id.is_empty() {
+                !included.passage_groups.is_empty()
+            } else {
+                included.section_index(id).is_some()
+            };
+            if !found {
+                bail!("`{target}` leads to a section that doesn't exist");
+            }
+        }
+        Ok(())
+    }
+
+    /// How the sections lead into one another.
+    pub fn graph(&self) -> &SectionGraph {
+        &self.graph
+    }
+
+    /// The index of the section with the given id.
+    pub fn section_index(&self, id: &str) -> Option<usize> {
+        self.graph.index(id)
+    }
+
+    /// The names of every section and choice whose visits are counted.
+    pub fn visit_names(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        for group in &self.passage_groups {
+            names.extend(group.id.clone());
+            for idx in 0..group.choices.as_ref().map(Vec::len).unwrap_or_default() {
+                names.extend(group.choice_visit_name(idx));
+            }
+        }
+        names
+    }
+
+    /// Make sure a `goto`, `call` or `next` points somewhere that exists.
+    fn check_target(&self, target: &str) -> Result<()> {
+        let (module, id) = split_target(target);
+        if let Some(module) = module {
+            // Sections in other files can only be checked once they've
+            // loaded.
+            if self.module_path(module).is_none() {
+                bail!("unknown file `{module}`");
+            }
+        } else if self.section_index(id).is_none() {
+            bail!("unknown section `{id}`");
+        }
+        Ok(())
+    }
+
+    /// Make sure every placeholder in some text names a known variable.
+    fn check_placeholders(&self, text: &str, visit_names: &HashSet<String>) -> Result<()> {
+        for placeholder in placeholders(text)? {
+            let name = placeholder.name;
+            if let Some(visited) = name
+                .strip_prefix("visits.")
+                .or_else(|| name.strip_prefix("seen."))
+            {
+                if !visit_names.contains(visited) {
+                    bail!("no section or choice called `{visited}` in `{text}`");
+                }
+            } else if !self.variables.contains_key(name) && !self.externs.iter().any(|e| e == name)
+            {
+                bail!("unknown variable `{name}` in `{text}`");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Elide consecutive lines of text.
+///
+/// Single line breaks are joined and double line breaks are converted to single
+/// line breaks.
+///
+/// A quirk of how this works is you'll find an extra space at the end of each
+/// line. In practice this might not matter to you, but in the case that it
+/// does... too bad!
+fn reflow_text(input: &str) -> String {
+    input.lines().fold(String::new(), |mut acc, s| {
+        let s = s.trim();
+        let sep = if s.is_empty() { "\n" } else { " " };
+        acc.push_str(s);
+        acc.push_str(sep);
+        acc
+    })
+}
+
+/// Given some amount of time, use the rate to determine how much of the time
+/// went unused and how many glyphs should now be revealed.
+pub(crate) fn calc_glyphs_to_reveal(delta_secs: f32, glyphs_per_sec: f32) -> (usize, f32) {
+    let reveal_how_many = (delta_secs * glyphs_per_sec).trunc();
+    let remainder = delta_secs - (reveal_how_many / glyphs_per_sec);
+    (reveal_how_many as usize, remainder)
+}
+
+/// How much of a passage is on screen so far.
+///
+/// Keeping track of this means each frame only has to add the glyphs revealed
+/// since the last one, rather than building the whole string over again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Revealed {
+    glyphs: usize,
+    /// Where the shown text ends in the passage.
+    bytes: usize,
+}
+
+impl Revealed {
+    /// Whether `shown` needs updating to match the play head.
+    pub fn is_behind(&self, head: usize) -> bool {
+        head != self.glyphs
+    }
+
+    /// Bring `shown` up to the first `head` glyphs of `text`, starting over if
+    /// the play head went backwards.
+    pub fn catch_up(&mut self, text: &str, head: usize, shown: &mut String) {
+        if head < self.glyphs {
+            shown.clear();
+            *self = Revealed::default();
+        }
+        let rest = &text[self.bytes..];
+        let len = rest
+            .char_indices()
+            .nth(head - self.glyphs)
+            .map_or(rest.len(), |(idx, _)| idx);
+        shown.push_str(&rest[..len]);
+        self.glyphs = head;
+        self.bytes += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// If the delta is not big enough to reveal at least one glyph, then the
+    /// remainder should be the entire delta.
+    #[test]
+    fn test_delta_carries_over() {
+        let (count, remainder) = calc_glyphs_to_reveal(1.0, 0.5);
+        assert_eq!(0, count);
+        assert_approx_eq!(1.0, remainder);
+    }
+
+    #[test]
+    fn test_delta_zero_when_glyph_revealed() {
+        let (count, remainder) = calc_glyphs_to_reveal(2.0, 0.5);
+        assert_eq!(1, count);
+        assert_approx_eq!(0.0, remainder);
+    }
+
+    #[test]
+    fn test_delta_remainder_when_glyph_revealed() {
+        let (count, remainder) = calc_glyphs_to_reveal(2.2, 0.5);
+        assert_eq!(1, count);
+        assert_approx_eq!(0.2, remainder);
+    }
+
+    #[test]
+    fn test_multi_glyph_remainder() {
+        let (count, remainder) = calc_glyphs_to_reveal(5.2, 2.0);
+        assert_eq!(10, count);
+        assert_approx_eq!(0.2, remainder);
+    }
+
+    #[test]
+    fn test_reveal_appends() {
+        let text = "Snaké? Snaaaké!";
+        let mut revealed = Revealed::default();
+        let mut shown = String::new();
+        for head in [0, 3, 5, 6, 6, 40] {
+            revealed.catch_up(text, head, &mut shown);
+            let expected: String = text.chars().take(head).collect();
+            assert_eq!(expected, shown);
+        }
+        assert!(!revealed.is_behind(40));
+        assert!(revealed.is_behind(0));
+        // Going back starts over.
+        revealed.catch_up(text, 2, &mut shown);
+        assert_eq!("Sn", shown);
+    }
+
+    #[test]
+    fn test_voices_match_passages() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["one", "two"]
+            voices = ["one.ogg"]
+            "#,
+        )
+        .unwrap();
+        let group = &dialogue.passage_groups[0];
+        assert_eq!(Some("one.ogg"), group.voice(0));
+        assert_eq!(None, group.voice(1));
+    }
+
+    #[test]
+    fn test_too_many_voices() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["one"]
+            voices = ["one.ogg", "two.ogg"]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_default_choice_out_of_range() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Quick!"]
+            timeout = 5.0
+            default = 2
+            choices = [{ label = "Left" }, { label = "Right" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_timeout_without_choices() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Quick!"]
+            timeout = 5.0
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_timeout_must_be_positive() {
+        for timeout in ["0.0", "-1.0", "nan", "inf"] {
+            let source = format!(
+                r#"
+                [[section]]
+                passages = ["Quick!"]
+                timeout = {timeout}
+                choices = [{{ label = "Left" }}]
+                "#
+            );
+            let err = Dialogue::from_slice(source.as_bytes()).unwrap_err();
+            assert!(
+                err.to_string().contains("positive number"),
+                "{timeout}: {err}"
+            );
+        }
+    }
+
+    fn choice(label: &str, once: bool, fallback: bool) -> Choice {
+        Choice {
+            id: None,
+            label: label.to_string(),
+            say: None,
+            speaker: None,
+            goto: None,
+            call: None,
+            returns: false,
+            once,
+            fallback,
+            condition_source: None,
+            condition: None,
+            check: None,
+            on_success: None,
+            on_failure: None,
+        }
+    }
+
+    #[test]
+    fn test_visible_choices_hides_fallbacks() {
+        let choices = [
+            choice("a", false, false),
+            choice("b", true, false),
+            choice("done", false, true),
+        ];
+        assert_eq!(vec![0, 1], visible_choices(&choices, |_| false, |_| true));
+        // Taking a regular choice doesn't remove it.
+        assert_eq!(
+            vec![0, 1],
+            visible_choices(&choices, |idx| idx == 0, |_| true)
+        );
+    }
+
+    #[test]
+    fn test_visible_choices_drops_taken_once() {
+        let choices = [
+            choice("a", true, false),
+            choice("b", true, false),
+            choice("done", false, true),
+        ];
+        assert_eq!(vec![1], visible_choices(&choices, |idx| idx == 0, |_| true));
+    }
+
+    #[test]
+    fn test_visible_choices_falls_back_when_exhausted() {
+        let choices = [
+            choice("a", true, false),
+            choice("b", true, false),
+            choice("done", false, true),
+        ];
+        assert_eq!(vec![2], visible_choices(&choices, |idx| idx < 2, |_| true));
+    }
+
+    #[test]
+    fn test_visible_choices_skips_disallowed() {
+        let choices = [
+            choice("a", false, false),
+            choice("b", false, false),
+            choice("done", false, true),
+        ];
+        assert_eq!(
+            vec![1],
+            visible_choices(&choices, |_| false, |idx| idx != 0)
+        );
+        assert_eq!(
+            vec![2],
+            visible_choices(&choices, |_| false, |idx| idx == 2)
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            externs = ["player_name"]
+            [variables]
+            gold = 0
+            [functions]
+            has_item = { args = ["text"] }
+            [[section]]
+            if = "gold > 3 || player_name == 'Snake'"
+            passages = ["?"]
+            choices = [{ label = "Open", if = "has_item('keycard')" }]
+            "#,
+        )
+        .unwrap();
+        let group = &dialogue.passage_groups[0];
+        assert_eq!(
+            "gold > 3 || player_name == 'Snake'",
+            group.condition.as_ref().unwrap().source
+        );
+        let choices = group.choices.as_ref().unwrap();
+        assert!(choices[0].condition.is_some());
+    }
+
+    #[test]
+    fn test_bad_condition_points_at_line() {
+        let result = Dialogue::from_slice(
+            br#"
+            [variables]
+            gold = 0
+
+            [[section]]
+            passages = ["?"]
+            choices = [
+                { label = "Buy", if = "gold == 'lots'" },
+            ]
+            "#,
+        );
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("line 8:"), "{message}");
+
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["?"]
+            if = "1 +"
+            "#,
+        );
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("line 4:"), "{message}");
+    }
+
+    #[test]
+    fn test_unclosed_sequence() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["{~a|b"]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_choice_jumps() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "shop"
+            passages = ["Anything else?"]
+            choices = [
+                { label = "Buy", call = "buy" },
+                { label = "Leave", goto = "shop" },
+                { label = "Back", return = true },
+                { label = "Hmm" },
+            ]
+            [[section]]
+            id = "buy"
+            passages = ["Here you go."]
+            "#,
+        )
+        .unwrap();
+        let jumps: Vec<_> = dialogue.passage_groups[0]
+            .choices
+            .iter()
+            .flatten()
+            .map(Choice::jump)
+            .collect();
+        assert_eq!(
+            vec![
+                Some(Jump::Call("buy".into())),
+                Some(Jump::Goto("shop".into())),
+                Some(Jump::Return),
+                None
+            ],
+            jumps
+        );
+    }
+
+    #[test]
+    fn test_section_jumps() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["Onwards."]
+            next = "c"
+            [[section]]
+            passages = ["Skipped."]
+            next = "$return"
+            [[section]]
+            id = "c"
+            passages = ["The end."]
+            end = true
+            [[section]]
+            passages = ["Falls through."]
+            "#,
+        )
+        .unwrap();
+        let jumps: Vec<_> = dialogue
+            .passage_groups
+            .iter()
+            .map(PassageGroup::jump)
+            .collect();
+        assert_eq!(
+            vec![
+                Some(Jump::Goto("c".into())),
+                Some(Jump::Return),
+                Some(Jump::End),
+                None
+            ],
+            jumps
+        );
+    }
+
+    #[test]
+    fn test_bad_section_jumps() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["?"]
+            next = "nowhere"
+            "#,
+        );
+        assert!(result.is_err());
+
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["?"]
+            next = "a"
+            end = true
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_odds() {
+        let check = Check {
+            stat: "persuasion".into(),
+            dc: 12,
+        };
+        // Needs a 10 or better.
+        assert_approx_eq!(0.55, check.odds(2));
+        assert_approx_eq!(1.0, check.odds(20));
+        assert_approx_eq!(0.0, check.odds(-10));
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let (roll, passed) = check.roll(2, &mut rng);
+            assert!((1..=Check::DIE).contains(&roll));
+            assert_eq!(roll >= 10, passed);
+        }
+    }
+
+    #[test]
+    fn test_check_outcomes() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Let me in."]
+            choices = [
+                { label = "Insist", check = { stat = "persuasion", dc = 12 }, on_success = "in", goto = "out" },
+            ]
+            [[section]]
+            id = "in"
+            passages = ["Fine."]
+            [[section]]
+            id = "out"
+            passages = ["No."]
+            "#,
+        )
+        .unwrap();
+        let choice = &dialogue.passage_groups[0].choices.as_ref().unwrap()[0];
+        assert_eq!(Some(Jump::Goto("in".into())), choice.jump_after_check(true));
+        assert_eq!(
+            Some(Jump::Goto("out".into())),
+            choice.jump_after_check(false)
+        );
+    }
+
+    #[test]
+    fn test_outcome_without_check() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["?"]
+            choices = [{ label = "Hmm", on_failure = "a" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_visit_names() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "shop"
+            passages = ["{visits.shop:plural(Once|# times)} now."]
+            choices = [
+                { id = "buy", label = "Buy", if = "!seen('shop.buy')" },
+                { label = "Leave", if = "visits('shop.1') < 3" },
+            ]
+            [[section]]
+            passages = ["Not counted."]
+            choices = [{ label = "Ok" }]
+            "#,
+        )
+        .unwrap();
+        let mut names: Vec<_> = dialogue.visit_names().into_iter().collect();
+        names.sort();
+        assert_eq!(vec!["shop", "shop.1", "shop.buy"], names);
+
+        let mut visits = VisitCounts::default();
+        visits.visit("shop");
+        visits.visit("shop");
+        assert_eq!(2, visits.get("shop"));
+        assert_eq!(Some(Value::Number(2.0)), visits.lookup("visits.shop"));
+        assert_eq!(Some(Value::Bool(false)), visits.lookup("seen.shop.buy"));
+        assert_eq!(None, visits.lookup("shop"));
+    }
+
+    #[test]
+    fn test_unknown_visit_name() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["Again?"]
+            if = "seen('b')"
+            "#,
+        );
+        assert!(result.is_err());
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["{visits.b}"]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_passage_speakers() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            speaker = "Para-Medic"
+            passages = [
+                "They're clones.",
+                { speaker = "Snake", text = "Clones?" },
+                { text = "Yes." },
+            ]
+            "#,
+        )
+        .unwrap();
+        let group = &dialogue.passage_groups[0];
+        assert_eq!("Clones?", group.passages[1].text.trim());
+        assert_eq!(Some("Para-Medic"), group.speaker_for(0));
+        assert_eq!(Some("Snake"), group.speaker_for(1));
+        assert_eq!(Some("Para-Medic"), group.speaker_for(2));
+    }
+
+    #[test]
+    fn test_choice_lines() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Who are you?"]
+            choices = [
+                { label = "[Lie] A friend.", say = "Just a friend of the family.", speaker = "Snake" },
+                { label = "Nobody." },
+            ]
+            "#,
+        )
+        .unwrap();
+        let choices = dialogue.passage_groups[0].choices.as_ref().unwrap();
+        assert_eq!(
+            Some("Just a friend of the family."),
+            choices[0].say.as_deref().map(str::trim)
+        );
+        assert_eq!(None, choices[1].say);
+
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Who are you?"]
+            choices = [{ label = "Nobody.", speaker = "Snake" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_text_input() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [variables]
+            password = ""
+            [[section]]
+            passages = ["What's the password?"]
+            input = { variable = "password", max_len = 10, allowed = "abcdefghijklmnopqrstuvwxyz", answer = "LaLiLuLeLo", ignore_case = true, on_match = "in", on_mismatch = "out" }
+            [[section]]
+            id = "in"
+            passages = ["Come in."]
+            [[section]]
+            id = "out"
+            passages = ["Get lost."]
+            "#,
+        )
+        .unwrap();
+        let input = dialogue.passage_groups[0].input.as_ref().unwrap();
+        assert!(input.accepts("lali", 'l'));
+        assert!(!input.accepts("lali", '1'));
+        assert!(!input.accepts("lalilulelo", 'l'));
+        assert!(input.validate("").is_err());
+        assert!(input.validate("   ").is_err());
+        assert!(input.validate("x").is_ok());
+        assert!(input.can_type("lalilulelo"));
+        assert_eq!(Some(Jump::Goto("in".into())), input.jump("lalilulelo"));
+        assert_eq!(Some(Jump::Goto("out".into())), input.jump("nope"));
+    }
+
+    #[test]
+    fn test_text_input_answer_must_fit() {
+        for input in [
+            r#"max_len = 8, answer = "lalilulelo""#,
+            r#"min_len = 12, answer = "lalilulelo""#,
+            r#"allowed = "abcdefghijklmnopqrstuvwxyz", answer = "lalilulelo!""#,
+            r#"allowed = "abcdefghijklmnopqrstuvwxyz", answer = "LaLiLuLeLo""#,
+        ] {
+            let src = format!(
+                r#"
+                [variables]
+                password = ""
+                [[section]]
+                passages = ["What's the password?"]
+                input = {{ variable = "password", {input} }}
+                "#
+            );
+            let err = Dialogue::from_slice(src.as_bytes()).unwrap_err();
+            assert!(err.to_string().contains("can't be typed"), "{input}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_text_input_needs_text_variable() {
+        for variables in ["", "password = 0"] {
+            let src = format!(
+                r#"
+                [variables]
+                {variables}
+                [[section]]
+                passages = ["What's the password?"]
+                input = {{ variable = "password" }}
+                "#
+            );
+            assert!(Dialogue::from_slice(src.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_modules() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [include]
+            shop = "npcs/shop.toml"
+            [[section]]
+            passages = ["?"]
+            choices = [
+                { label = "Shop", goto = "shop#greeting" },
+                { label = "Inn", call = "inn.toml#" },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(vec!["shop", "inn.toml"], dialogue.modules());
+        assert_eq!(Some("npcs/shop.toml"), dialogue.module_path("shop"));
+        assert_eq!(Some("inn.toml"), dialogue.module_path("inn.toml"));
+        assert_eq!((Some("inn.toml"), ""), split_target("inn.toml#"));
+        assert_eq!((None, "greeting"), split_target("greeting"));
+    }
+
+    #[test]
+    fn test_module_targets() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [include]
+            shop = "npcs/shop.toml"
+            [[section]]
+            passages = ["?"]
+            choices = [
+                { label = "Shop", goto = "shop#counter" },
+                { label = "Browse", call = "shop#" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let shop = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "counter"
+            passages = ["Welcome."]
+            "#,
+        )
+        .unwrap();
+        assert!(dialogue.check_module_targets("shop", &shop).is_ok());
+
+        let typo = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "countr"
+            passages = ["Welcome."]
+            "#,
+        )
+        .unwrap();
+        let err = dialogue.check_module_targets("shop", &typo).unwrap_err();
+        assert!(err.to_string().contains("`shop#counter`"), "{err}");
+
+        let barks_only =
+            Dialogue::from_slice(b"[bark.hello]\nlines = [{ text = \"Hi.\" }]").unwrap();
+        assert!(dialogue.check_module_targets("shop", &barks_only).is_err());
+    }
+
+    #[test]
+    fn test_unknown_module() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["?"]
+            choices = [{ label = "Shop", goto = "shop#greeting" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_choice_with_two_jumps() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            id = "a"
+            passages = ["?"]
+            choices = [{ label = "Both", goto = "a", call = "a" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_choice_to_unknown_section() {
+        let result = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["?"]
+            choices = [{ label = "Nowhere", call = "b" }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_section_hooks() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["Welcome!"]
+            on_enter = ["await open_door"]
+            on_exit = ["give_item key"]
+            "#,
+        )
+        .unwrap();
+        let group = &dialogue.passage_groups[0];
+        assert_eq!("open_door", group.on_enter[0].name);
+        assert!(group.on_enter[0].wait);
+        assert_eq!(vec!["key"], group.on_exit[0].args);
+    }
+
+    #[test]
+    fn test_placeholders_must_be_declared() {
+        let src = br#"
+            externs = ["player_name"]
+            [variables]
+            gold = 0
+            [[section]]
+            passages = ["Hi {player_name}, you have {gold} gold."]
+            choices = [{ label = "Spend {gold:n}" }, { label = "Give {amount}" }]
+            "#;
+        let err = Dialogue::from_slice(src).unwrap_err();
+        assert!(err.to_string().contains("amount"), "{err}");
+    }
+
+    #[test]
+    fn test_cycle_variations() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut variations = Variations::default();
+        let picks: Vec<usize> = (0..5)
+            .map(|_| variations.next(PassageMode::Cycle, 3, &mut rng))
+            .collect();
+        assert_eq!(vec![0, 1, 2, 0, 1], picks);
+    }
+
+    #[test]
+    fn test_shuffle_variations_use_every_option() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut variations = Variations::default();
+        let picks: Vec<usize> = (0..12)
+            .map(|_| variations.next(PassageMode::Shuffle, 4, &mut rng))
+            .collect();
+        for round in picks.chunks(4) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(vec![0, 1, 2, 3], round);
+        }
+        for pair in picks.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_visit_len() {
+        let dialogue = Dialogue::from_slice(
+            br#"
+            [[section]]
+            passages = ["a", "b"]
+            [[section]]
+            mode = "shuffle"
+            passages = ["a", "b"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(2, dialogue.passage_groups[0].visit_len());
+        assert_eq!(1, dialogue.passage_groups[1].visit_len());
+    }
+
+    fn bark_pool(src: &str) -> BarkPool {
+        let mut dialogue = Dialogue::from_slice(src.as_bytes()).unwrap();
+        dialogue.barks.remove("hello").unwrap()
+    }
+
+    #[test]
+    fn test_bark_pool_cooldown() {
+        let pool = bark_pool(
+            r#"
+            [bark.hello]
+            cooldown = 5.0
+            lines = [{ text = "Hi." }]
+            "#,
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut history = BarkHistory::default();
+        assert_eq!(Some(0), pool.pick(&mut history, 0.0, &mut rng));
+        assert_eq!(None, pool.pick(&mut history, 4.0, &mut rng));
+        assert_eq!(Some(0), pool.pick(&mut history, 5.0, &mut rng));
+    }
+
+    #[test]
+    fn test_bark_line_cooldown() {
+        let pool = bark_pool(
+            r#"
+            [bark.hello]
+            lines = [{ text = "Hi.", cooldown = 10.0 }, { text = "Hey." }]
+            "#,
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut history = BarkHistory::default();
+        let picks: Vec<usize> = (0..10)
+            .map(|t| pool.pick(&mut history, f64::from(t), &mut rng).unwrap())
+            .collect();
+        assert!(picks.iter().filter(|&&idx| idx == 0).count() <= 1);
+    }
+
+    #[test]
+    fn test_bark_no_repeat() {
+        let pool = bark_pool(
+            r#"
+            [bark.hello]
+            no_repeat = 2
+            lines = [{ text = "a" }, { text = "b" }, { text = "c" }]
+            "#,
+        );
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut history = BarkHistory::default();
+        let picks: Vec<usize> = (0..30)
+            .map(|t| pool.pick(&mut history, f64::from(t), &mut rng).unwrap())
+            .collect();
+        for window in picks.windows(3) {
+            assert_ne!(window[0], window[1]);
+            assert_ne!(window[0], window[2]);
+            assert_ne!(window[1], window[2]);
+        }
+    }
+
+    #[test]
+    fn test_bark_seeded_picks_repeat() {
+        let pool = bark_pool(
+            r#"
+            [bark.hello]
+            lines = [{ text = "a", weight = 3.0 }, { text = "b" }, { text = "c" }]
+            "#,
+        );
+        let run = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut history = BarkHistory::default();
+            (0..20)
+                .map(|t| pool.pick(&mut history, f64::from(t), &mut rng).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(42), run(42));
+    }
+
+    #[test]
+    fn test_bark_needs_positive_weight() {
+        let result = Dialogue::from_slice(
+            br#"
+            [bark.hello]
+            lines = [{ text = "Hi.", weight = 0.0 }]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_no_sections_or_passages() {
+        // Barks don't need any sections to go with them.
+        let dialogue = Dialogue::from_slice(b"[bark.hello]\nlines = [{ text = \"Hi.\" }]").unwrap();
+        assert!(dialogue.passage_groups.is_empty());
+
+        let err = Dialogue::from_slice(b"[[section]]\npassages = []").unwrap_err();
+        assert!(err.to_string().contains("no passages"), "{err}");
+    }
+
+    #[test]
+    fn test_reflow_single_line() {
+        assert_eq!("abc", reflow_text("abc").trim());
+    }
+
+    #[test]
+    fn test_reflow_lines_no_blanks() {
+        assert_eq!(
+            "abc def ",
+            reflow_text(
+                "
+            abc
+            def
+            "
+                .trim()
+            )
+        );
+    }
+
+    #[test]
+    fn test_reflow_lines_with_blanks() {
+        assert_eq!(
+            "\
+abc def 
+ghi jkl \
+",
+            reflow_text(
+                "
+    abc
+    def
+    
+    ghi
+    jkl
+    "
+                .trim()
+            )
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Working out the final text for a passage: picking from inline sequences
+//! and filling in placeholders.
+
+use super::PassageMode;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A value that can be filled in to a placeholder.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{b}"),
+            // Whole numbers are far more common than not, so leave off the
+            // trailing `.0`.
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(n.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+/// How to show the value filled in to a placeholder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format<'a> {
+    /// `{gold}`
+    Plain,
+    /// `{gold:n}` groups the digits with commas, as in `12,000`.
+    Grouped,
+    /// `{ratio:.2}` shows a fixed number of decimal places.
+    Fixed(usize),
+    /// `{gold:plural(# coin|# coins)}` picks a form depending on whether the
+    /// value is one, with `#` standing in for the value itself.
+    Plural { one: &'a str, other: &'a str },
+}
+
+/// A `{name}` or `{name:format}` in some text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placeholder<'a> {
+    pub name: &'a str,
+    pub format: Format<'a>,
+}
+
+impl<'a> Placeholder<'a> {
+    /// The name part of what's inside some braces, if it looks like a
+    /// placeholder at all.
+    fn name(inner: &str) -> Option<&str> {
+        let name = inner.split_once(':').map_or(inner, |(name, _)| name).trim();
+        // Dots are for built-in values like `visits.intro`.
+        let valid = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        (!name.is_empty() && name.chars().all(valid)).then_some(name)
+    }
+
+    /// Parse the inside of the braces.
+    fn parse(inner: &'a str) -> Result<Self> {
+        let Some(name) = Placeholder::name(inner) else {
+            bail!("`{{{inner}}}` isn't a valid placeholder");
+        };
+        let spec = inner.split_once(':').map(|(_, spec)| spec.trim());
+        let format = match spec {
+            None => Format::Plain,
+            Some("n") => Format::Grouped,
+            Some(spec) if spec.starts_with('.') => Format::Fixed(
+                spec[1..]
+                    .parse()
+                    .map_err(|_| anyhow!("bad decimal places in `{{{inner}}}`"))?,
+            ),
+            Some(spec) => {
+                let forms = spec
+                    .strip_prefix("plural(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .and_then(|forms| forms.split_once('|'));
+                match forms {
+                    Some((one, other)) => Format::Plural { one, other },
+                    None => bail!("unknown format in `{{{inner}}}`"),
+                }
+            }
+        };
+        Ok(Placeholder { name, format })
+    }
+
+    fn render(&self, value: &Value) -> String {
+        match (self.format, value) {
+            (Format::Grouped, Value::Number(n)) => group_digits(&Value::Number(*n).to_string()),
+            (Format::Fixed(places), Value::Number(n)) => format!("{n:.places$}"),
+            (Format::Plural { one, other }, value) => {
+                let form = if *value == Value::Number(1.0) {
+                    one
+                } else {
+                    other
+                };
+                form.replace('#', &value.to_string())
+            }
+            (_, value) => value.to_string(),
+        }
+    }
+}
+
+/// Put commas between each group of three digits in the whole part of a
+/// number.
+fn group_digits(number: &str) -> String {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", number),
+    };
+    let (whole, fraction) = match digits.find('.') {
+        Some(idx) => digits.split_at(idx),
+        None => (digits, ""),
+    };
+    let mut out = String::from(sign);
+    for (idx, c) in whole.chars().enumerate() {
+        if idx > 0 && (whole.len() - idx) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out.push_str(fraction);
+    out
+}
+
+/// Find the `}` matching the `{` that `text` starts with.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split the options of a sequence, leaving alone any `|` inside a
+/// placeholder.
+fn split_options(text: &str) -> Vec<&str> {
+    let mut options = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                options.push(&text[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    options.push(&text[start..]);
+    options
+}
+
+/// The pieces some text breaks down into.
+enum Segment<'a> {
+    Text(&'a str),
+    Sequence(PassageMode, Vec<&'a str>),
+    /// The inside of a pair of braces around a name, with or without a
+    /// format.
+    Placeholder(&'a str),
+}
+
+/// Break some text down into sequences, placeholders and the text around
+/// them.
+///
+/// Any other `{`, such as one that's never closed or that has a sentence
+/// inside it, is just text.
+fn segments(text: &str) -> Result<Vec<Segment<'_>>> {
+    let mut out = vec![];
+    let mut rest = text;
+    let mut from = 0;
+    while let Some(start) = rest[from..].find('{').map(|idx| from + idx) {
+        let closed = closing_brace(&rest[start..]);
+        let mode = match rest[start + 1..].chars().next() {
+            Some('~') => Some(PassageMode::Shuffle),
+            Some('&') => Some(PassageMode::Cycle),
+            _ => None,
+        };
+        let segment = match (closed, mode) {
+            (None, Some(_)) => bail!("unclosed sequence in `{text}`"),
+            (Some(len), Some(mode)) => {
+                Segment::Sequence(mode, split_options(&rest[start + 2..start + len]))
+            }
+            (Some(len), None) if Placeholder::name(&rest[start + 1..start + len]).is_some() => {
+                Segment::Placeholder(&rest[start + 1..start + len])
+            }
+            _ => {
+                // Carry on looking after the brace, keeping it as text.
+                from = start + 1;
+                continue;
+            }
+        };
+        let len = closed.expect("only closed braces get this far");
+        out.push(Segment::Text(&rest[..start]));
+        out.push(segment);
+        rest = &rest[start + len + 1..];
+        from = 0;
+    }
+    out.push(Segment::Text(rest));
+    Ok(out)
+}
+
+/// Replace each inline sequence in some text with one of its options.
+///
+/// `{~a|b|c}` shuffles through the options and `{&a|b|c}` cycles through them.
+/// `pick` is given the position of the sequence within the text, how to pick
+/// and the number of options, and returns the option to use.
+///
+/// Placeholders are left for `interpolate` to fill in, and any other braces
+/// are left as they are.
+pub fn resolve_sequences(
+    text: &str,
+    mut pick: impl FnMut(usize, PassageMode, usize) -> usize,
+) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut seq = 0;
+    for segment in segments(text)? {
+        match segment {
+            Segment::Text(s) => out.push_str(s),
+            Segment::Sequence(mode, options) => {
+                out.push_str(options[pick(seq, mode, options.len())]);
+                seq += 1;
+            }
+            Segment::Placeholder(inner) => {
+                out.push('{');
+                out.push_str(inner);
+                out.push('}');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Every placeholder in some text, including those inside sequences.
+pub fn placeholders(text: &str) -> Result<Vec<Placeholder<'_>>> {
+    let mut out = vec![];
+    for segment in segments(text)? {
+        match segment {
+            Segment::Text(_) => {}
+            Segment::Sequence(_, options) => {
+                for option in options {
+                    out.extend(placeholders(option)?);
+                }
+            }
+            Segment::Placeholder(inner) => out.push(Placeholder::parse(inner)?),
+        }
+    }
+    Ok(out)
+}
+
+/// Fill in the placeholders in some text, once any sequences have been
+/// resolved.
+///
+/// Placeholders that `lookup` has no value for are left as they are.
+pub fn interpolate(text: &str, lookup: impl Fn(&str) -> Option<Value>) -> String {
+    let Ok(segments) = segments(text) else {
+        return text.to_string();
+    };
+    let mut out = String::with_capacity(text.len());
+    for segment in segments {
+        match segment {
+            Segment::Text(s) => out.push_str(s),
+            Segment::Placeholder(inner) => {
+                let value = Placeholder::parse(inner)
+                    .ok()
+                    .and_then(|p| Some((p, lookup(p.name)?)));
+                match value {
+                    Some((placeholder, value)) => out.push_str(&placeholder.render(&value)),
+                    None => {
+                        out.push('{');
+                        out.push_str(inner);
+                        out.push('}');
+                    }
+                }
+            }
+            // Sequences should have been picked from by now.
+            Segment::Sequence(..) => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<Value> {
+        match name {
+            "player_name" => Some("Snake".into()),
+            "gold" => Some(12000.into()),
+            "keys" => Some(1.into()),
+            "ratio" => Some(Value::Number(0.4567)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_sequences() {
+        let mut seen = vec![];
+        let text = resolve_sequences(
+            "{&Hi|Hello}, {name}. {~Nice|Fine} {day",
+            |seq, mode, count| {
+                seen.push((seq, mode, count));
+                count - 1
+            },
+        )
+        .unwrap();
+        assert_eq!("Hello, {name}. Fine {day", text);
+        assert_eq!(
+            vec![(0, PassageMode::Cycle, 2), (1, PassageMode::Shuffle, 2)],
+            seen
+        );
+    }
+
+    #[test]
+    fn test_unclosed_sequence() {
+        assert!(resolve_sequences("{~a|b", |_, _, _| 0).is_err());
+        assert!(placeholders("Hi {&there").is_err());
+    }
+
+    #[test]
+    fn test_literal_braces() {
+        for text in ["Hi {name", "{not a name}", "{ } and {player_name"] {
+            assert!(placeholders(text).unwrap().is_empty(), "{text}");
+            assert_eq!(text, resolve_sequences(text, |_, _, _| 0).unwrap());
+            assert_eq!(text, interpolate(text, lookup));
+        }
+        assert_eq!(
+            "{Snake} {gold",
+            interpolate("{{player_name}} {gold", lookup)
+        );
+    }
+
+    #[test]
+    fn test_interpolate_plain() {
+        assert_eq!(
+            "Welcome back, Snake. You have 12000 gold.",
+            interpolate("Welcome back, {player_name}. You have {gold} gold.", lookup)
+        );
+    }
+
+    #[test]
+    fn test_interpolate_unknown_left_alone() {
+        assert_eq!("Hi {who}.", interpolate("Hi {who}.", lookup));
+    }
+
+    #[test]
+    fn test_interpolate_formats() {
+        assert_eq!("12,000", interpolate("{gold:n}", lookup));
+        assert_eq!("0.46", interpolate("{ratio:.2}", lookup));
+        assert_eq!(
+            "1 key, 12000 coins",
+            interpolate(
+                "{keys:plural(# key|# keys)}, {gold:plural(# coin|# coins)}",
+                lookup
+            )
+        );
+    }
+
+    #[test]
+    fn test_placeholders_inside_sequences() {
+        let names: Vec<&str> = placeholders("{~Hi {player_name}|{gold:plural(one|many)}}")
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(vec!["player_name", "gold"], names);
+    }
+
+    #[test]
+    fn test_bad_format() {
+        assert!(placeholders("{gold:wat}").is_err());
+        assert!(placeholders("{gold:.x}").is_err());
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!("1", group_digits("1"));
+        assert_eq!("999", group_digits("999"));
+        assert_eq!("1,000", group_digits("1000"));
+        assert_eq!("-1,234,567.5", group_digits("-1234567.5"));
+    }
+}